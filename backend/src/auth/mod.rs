@@ -1,70 +1,189 @@
+pub mod keys;
+pub mod oidc;
+pub mod rbac;
+pub mod throttle;
+pub mod tickets;
+pub mod tokens;
+
 use crate::models::*;
 use anyhow::{anyhow, Result};
-use jsonwebtoken::{encode, EncodingKey, Header};
-use serde::{Deserialize, Serialize};
-use ssh2::Session;
-use std::net::TcpStream;
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: String, // username
-    pub exp: usize,
+use oidc::OidcProvider;
+use rbac::{linux_groups, RbacPolicy};
+use tokens::{Identity, IssuedTokens, TokenStore};
+
+use crate::{
+    config::SshConfig,
+    ssh::{ca::SshCa, SshAuth},
+    telemetry::{self, scrub::Secret},
+    terminal::connection,
+};
+
+/// SSH 서버 연결 및 인증, 요청 시 리눅스 그룹 조회.
+/// 바깥 오류는 인증 실패, 안쪽 오류는 그룹 조회 실패입니다.
+fn verify_ssh_credentials(
+    ssh: &SshConfig,
+    username: &str,
+    password: &Secret,
+    with_groups: bool,
+) -> Result<Result<Vec<String>>> {
+    // 기본 SSH 서버(ssh.host / ssh.port)에 연결 후 비밀번호로 인증
+    let auth = SshAuth::Password(password.clone());
+    let session = connection::connect(&ssh.default_target(), username, &auth)?;
+
+    // 역할 정책이 그룹을 참조할 때만 조회
+    if !with_groups {
+        return Ok(Ok(Vec::new()));
+    }
+    Ok(linux_groups(&session))
 }
 
-/// SSH 서버 연결 및 인증
-fn verify_ssh_credentials(username: &str, password: &str) -> Result<bool> {
-    // SSH 서버 주소 (환경변수로 설정 가능, 기본값은 localhost)
-    let ssh_host = std::env::var("SSH_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let ssh_port = std::env::var("SSH_PORT")
-        .unwrap_or_else(|_| "22".to_string())
-        .parse::<u16>()
-        .unwrap_or(22);
-
-    // TCP 연결
-    let tcp = TcpStream::connect((ssh_host.as_str(), ssh_port))
-        .map_err(|e| anyhow!("Failed to connect to SSH server: {}", e))?;
-
-    // SSH 세션 생성
-    let mut session = Session::new().map_err(|e| anyhow!("Failed to create SSH session: {}", e))?;
-    session.set_tcp_stream(tcp);
-    session
-        .handshake()
-        .map_err(|e| anyhow!("SSH handshake failed: {}", e))?;
-
-    // 사용자 인증 시도
-    session
-        .userauth_password(username, password)
-        .map_err(|e| anyhow!("SSH authentication failed: {}", e))?;
-
-    // 인증 성공 확인
-    if session.authenticated() {
-        Ok(true)
+/// Linux groups of an SSO user, looked up with the credentials the server holds
+async fn sso_groups(
+    ssh: &SshConfig,
+    ssh_ca: Option<&SshCa>,
+    username: &str,
+) -> Result<Vec<String>> {
+    let auth = SshAuth::from_server(ssh, ssh_ca, username, true)?
+        .ok_or_else(|| anyhow!("No SSH credentials are available for {}", username))?;
+
+    let (user, target) = (username.to_string(), ssh.default_target());
+    telemetry::spawn_blocking(move || {
+        let session = connection::connect(&target, &user, &auth)?;
+        linux_groups(&session).map_err(|e| anyhow!(e))
+    })
+    .await
+    .map_err(|e| anyhow!("Group lookup task failed: {}", e))?
+}
+
+/// Role and host restriction from the RBAC policy. `groups` is only looked up when the
+/// policy refers to groups, and then a failed lookup fails the login: resolving without
+/// them would skip every `@group` host restriction and fall back to `default_role`.
+fn identity(
+    rbac: &RbacPolicy,
+    username: String,
+    sso: bool,
+    groups: Result<Vec<String>>,
+) -> Result<Identity> {
+    let groups = groups.map_err(|e| {
+        tracing::warn!("Failed to look up groups of {}: {:#}", username, e);
+        anyhow!(
+            "Could not look up the groups of {}, try again later",
+            username
+        )
+    })?;
+    let (role, hosts) = rbac.resolve(&username, &groups);
+    tracing::info!("User {} has role {:?}", username, role);
+
+    Ok(Identity {
+        username,
+        sso,
+        role,
+        hosts,
+    })
+}
+
+/// Authenticate user via SSH and issue access/refresh tokens (no database required)
+#[tracing::instrument(name = "login", skip_all, fields(user = %req.username))]
+pub async fn authenticate_user(
+    tokens: &TokenStore,
+    rbac: &RbacPolicy,
+    ssh: &SshConfig,
+    req: LoginRequest,
+) -> Result<LoginResponse> {
+    // SSH를 통한 실제 리눅스 계정 인증 (libssh2는 블로킹)
+    let (ssh, with_groups) = (ssh.clone(), rbac.uses_groups());
+    let (username, password) = (req.username.clone(), req.password.clone());
+    let groups = telemetry::spawn_blocking(move || {
+        verify_ssh_credentials(&ssh, &username, &password, with_groups)
+    })
+    .await??;
+
+    let identity = identity(rbac, req.username, false, groups)?;
+    let issued = tokens.issue(&identity)?;
+
+    Ok(login_response(issued, identity))
+}
+
+/// Finish an OpenID Connect login and issue tokens for the mapped Linux user.
+/// SSH access then uses a CA certificate or the user's key, so one of them must be available.
+#[tracing::instrument(name = "sso_login", skip_all, fields(user = tracing::field::Empty))]
+pub async fn sso_login(
+    tokens: &TokenStore,
+    oidc: &OidcProvider,
+    ssh_ca: Option<&SshCa>,
+    rbac: &RbacPolicy,
+    ssh: &SshConfig,
+    code: &str,
+    state: &str,
+) -> Result<LoginResponse> {
+    let username = oidc.complete(code, state).await?;
+    tracing::Span::current().record("user", username.as_str());
+
+    if ssh_ca.is_none() && SshAuth::user_key(ssh, &username).is_none() {
+        tracing::warn!("SSO login for {} rejected: no SSH key configured", username);
+        return Err(anyhow!("No SSH key is configured for {}", username));
+    }
+
+    let groups = if rbac.uses_groups() {
+        sso_groups(ssh, ssh_ca, &username).await
     } else {
-        Err(anyhow!("SSH authentication failed"))
+        Ok(Vec::new())
+    };
+
+    let identity = identity(rbac, username, true, groups)?;
+    let issued = tokens.issue(&identity)?;
+    tracing::info!("User {} logged in via SSO", identity.username);
+
+    Ok(login_response(issued, identity))
+}
+
+/// Rotate a refresh token into a new token pair
+pub async fn refresh_session(tokens: &TokenStore, req: RefreshRequest) -> Result<LoginResponse> {
+    let (identity, issued) = tokens.refresh(&req.refresh_token)?;
+
+    Ok(login_response(issued, identity))
+}
+
+fn login_response(issued: IssuedTokens, identity: Identity) -> LoginResponse {
+    LoginResponse {
+        token: issued.access_token,
+        refresh_token: issued.refresh_token,
+        expires_in: issued.expires_in,
+        username: identity.username,
+        role: identity.role,
     }
 }
 
-/// Authenticate user via SSH and generate JWT token (no database required)
-pub async fn authenticate_user(req: LoginRequest) -> Result<LoginResponse> {
-    // SSH를 통한 실제 리눅스 계정 인증
-    verify_ssh_credentials(&req.username, &req.password)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rbac::Role;
 
-    // Generate JWT token with username as subject
-    let claims = Claims {
-        sub: req.username.clone(), // username directly in JWT
-        exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-    };
+    fn policy() -> RbacPolicy {
+        serde_json::from_str(
+            r#"{
+                "default_role": "operator",
+                "groups": { "contractors": "viewer" },
+                "hosts": { "@contractors": ["sandbox.internal"] }
+            }"#,
+        )
+        .unwrap()
+    }
 
-    let secret = std::env::var("JWT_SECRET")
-        .expect("JWT_SECRET environment variable must be set for production use");
-    let token = encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )?;
-
-    Ok(LoginResponse {
-        token,
-        username: req.username,
-    })
+    #[test]
+    fn group_restrictions_apply_when_the_lookup_succeeds() {
+        let groups = Ok(vec!["contractors".to_string()]);
+        let identity = identity(&policy(), "carol".to_string(), false, groups).unwrap();
+
+        assert_eq!(identity.role, Role::Viewer);
+        assert_eq!(identity.hosts, Some(vec!["sandbox.internal".to_string()]));
+    }
+
+    #[test]
+    fn failed_group_lookup_fails_the_login() {
+        let groups = Err(anyhow!("id -Gn failed"));
+
+        // Resolving without groups would make carol an operator on every host
+        assert!(identity(&policy(), "carol".to_string(), true, groups).is_err());
+    }
 }
@@ -0,0 +1,588 @@
+use std::{collections::HashMap, sync::RwLock, time::Duration as StdDuration};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+use url::Url;
+use uuid::Uuid;
+
+use crate::config::OidcConfig;
+
+/// How long the user has to finish logging in at the identity provider
+const LOGIN_TIMEOUT_MINUTES: i64 = 10;
+
+/// Endpoints from `/.well-known/openid-configuration`
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Authorization request waiting for its callback, keyed by `state`
+struct PendingLogin {
+    nonce: String,
+    /// PKCE code verifier
+    verifier: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Authorization code flow (with PKCE) against the company identity provider.
+/// A successful login yields the Linux username the deuseda tokens are issued for.
+pub struct OidcProvider {
+    /// Without the trailing slash, as compared against the discovery document
+    issuer: String,
+    config: OidcConfig,
+    /// Pinned, so the token header cannot pick a weaker or symmetric algorithm
+    algorithms: Vec<Algorithm>,
+    http: reqwest::Client,
+    metadata: OnceCell<ProviderMetadata>,
+    /// Identity provider signing keys, refetched when an unknown `kid` shows up
+    jwks: RwLock<JwkSet>,
+    pending: RwLock<HashMap<String, PendingLogin>>,
+}
+
+impl OidcProvider {
+    /// `None` unless `auth.oidc.issuer` is set
+    pub fn new(config: &OidcConfig) -> Result<Option<Self>> {
+        let Some(issuer) = &config.issuer else {
+            return Ok(None);
+        };
+        let issuer = issuer.trim_end_matches('/').to_string();
+
+        tracing::info!("OpenID Connect login enabled (issuer: {})", issuer);
+
+        let http = reqwest::Client::builder()
+            .timeout(StdDuration::from_secs(10))
+            .build()?;
+
+        Ok(Some(Self {
+            issuer,
+            config: config.clone(),
+            algorithms: id_token_algorithms(config)?,
+            http,
+            metadata: OnceCell::new(),
+            jwks: RwLock::new(JwkSet { keys: Vec::new() }),
+            pending: RwLock::new(HashMap::new()),
+        }))
+    }
+
+    /// Discover the provider endpoints once
+    async fn metadata(&self) -> Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer);
+                let metadata: ProviderMetadata = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .with_context(|| format!("Invalid discovery document at {}", url))?;
+
+                if metadata.issuer.trim_end_matches('/') != self.issuer {
+                    bail!("Discovery document is for issuer {}", metadata.issuer);
+                }
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Start a login: remember state, nonce and PKCE verifier and build the
+    /// identity provider URL to send the browser to
+    pub async fn authorization_url(&self) -> Result<String> {
+        let metadata = self.metadata().await?;
+
+        let state = Uuid::new_v4().simple().to_string();
+        let nonce = Uuid::new_v4().simple().to_string();
+        let verifier = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", &self.config.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending = self.pending.write().unwrap();
+        let now = Utc::now();
+        pending.retain(|_, p| p.expires_at > now);
+        pending.insert(
+            state,
+            PendingLogin {
+                nonce,
+                verifier,
+                expires_at: now + Duration::minutes(LOGIN_TIMEOUT_MINUTES),
+            },
+        );
+
+        Ok(url.into())
+    }
+
+    /// Finish a login from the callback and return the mapped Linux username
+    pub async fn complete(&self, code: &str, state: &str) -> Result<String> {
+        let pending = self
+            .pending
+            .write()
+            .unwrap()
+            .remove(state)
+            .filter(|p| p.expires_at > Utc::now())
+            .ok_or_else(|| anyhow!("Login request expired, please try again"))?;
+
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("code_verifier", &pending.verifier),
+        ];
+        let mut request = self.http.post(&metadata.token_endpoint);
+        match &self.config.client_secret {
+            Some(secret) => {
+                request = request.basic_auth(&self.config.client_id, Some(secret.expose()))
+            }
+            None => form.push(("client_id", &self.config.client_id)),
+        }
+
+        let response = request.form(&form).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            tracing::warn!("OIDC token exchange failed ({}): {}", status, body);
+            bail!("Token exchange with the identity provider failed");
+        }
+        let tokens: TokenResponse = response.json().await?;
+
+        let claims = self
+            .verify_id_token(&tokens.id_token, &pending.nonce)
+            .await?;
+        self.check_groups(&claims)?;
+        self.username(&claims)
+    }
+
+    async fn verify_id_token(&self, id_token: &str, nonce: &str) -> Result<Map<String, Value>> {
+        let metadata = self.metadata().await?;
+        let header = decode_header(id_token)?;
+        if !self.algorithms.contains(&header.alg) {
+            bail!(
+                "ID token is signed with {:?}, which is not allowed",
+                header.alg
+            );
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        // Only the token's own algorithm, already checked against the list above:
+        // jsonwebtoken rejects a list that mixes key families (RSA, EC, EdDSA)
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+
+        let claims = decode::<Map<String, Value>>(id_token, &key, &validation)?.claims;
+        if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+            bail!("ID token nonce does not match");
+        }
+
+        Ok(claims)
+    }
+
+    /// Signing key for an ID token; the key set is refetched once for unknown key ids
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        if let Some(key) = self.find_key(kid) {
+            return key;
+        }
+
+        let metadata = self.metadata().await?;
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        *self.jwks.write().unwrap() = jwks;
+
+        self.find_key(kid)
+            .ok_or_else(|| anyhow!("Unknown ID token signing key"))?
+    }
+
+    fn find_key(&self, kid: Option<&str>) -> Option<Result<DecodingKey>> {
+        let jwks = self.jwks.read().unwrap();
+        let jwk = match kid {
+            Some(kid) => jwks.find(kid),
+            // Without a key id only an unambiguous key set will do
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }?;
+
+        Some(DecodingKey::from_jwk(jwk).map_err(Into::into))
+    }
+
+    fn check_groups(&self, claims: &Map<String, Value>) -> Result<()> {
+        if self.config.allowed_groups.is_empty() {
+            return Ok(());
+        }
+
+        let groups: Vec<&str> = match claims.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(group)) => vec![group.as_str()],
+            _ => Vec::new(),
+        };
+
+        if groups.iter().any(|g| {
+            self.config
+                .allowed_groups
+                .iter()
+                .any(|allowed| allowed == g)
+        }) {
+            Ok(())
+        } else {
+            bail!("You are not a member of a group allowed to use this console")
+        }
+    }
+
+    /// Map the configured claim to a Linux username
+    fn username(&self, claims: &Map<String, Value>) -> Result<String> {
+        let value = claims
+            .get(&self.config.username_claim)
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("ID token has no {} claim", self.config.username_claim))?;
+
+        let value = match value.split_once('@') {
+            Some((local, _)) if self.config.username_strip_domain => local,
+            _ => value,
+        };
+        let username = value.to_lowercase();
+
+        if !is_linux_username(&username) {
+            bail!("{} is not a valid Linux username", username);
+        }
+        Ok(username)
+    }
+
+    /// Frontend URL carrying the login result in its fragment, out of server logs and referrers
+    pub fn post_login_redirect(&self, params: &[(&str, &str)]) -> String {
+        let fragment = url::form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(params)
+            .finish();
+
+        format!("{}#{}", self.config.post_login_url, fragment)
+    }
+}
+
+/// `auth.oidc.id_token_algorithms`, parsed. Shared-secret algorithms are refused: the
+/// identity provider's keys are public, so anyone could sign with them.
+pub fn id_token_algorithms(config: &OidcConfig) -> Result<Vec<Algorithm>> {
+    if config.id_token_algorithms.is_empty() {
+        bail!("auth.oidc.id_token_algorithms must not be empty");
+    }
+    config
+        .id_token_algorithms
+        .iter()
+        .map(|name| {
+            let alg: Algorithm = name
+                .parse()
+                .map_err(|_| anyhow!("Unknown ID token algorithm {}", name))?;
+            if matches!(alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+                bail!("ID token algorithm {} is not allowed", name);
+            }
+            Ok(alg)
+        })
+        .collect()
+}
+
+/// `[a-z_][a-z0-9_-]*`, at most 32 characters (useradd's default rule)
+fn is_linux_username(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid_first = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_');
+
+    valid_first
+        && name.len() <= 32
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        extract::{Query, State},
+        http::{header, StatusCode},
+        response::{IntoResponse, Redirect, Response},
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    /// RFC 8032 test vector 1
+    const ED25519_SEED: &str = "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60";
+    const ED25519_PUBLIC: &str = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// How the mock identity provider misbehaves
+    #[derive(Clone, Copy, PartialEq)]
+    enum Fault {
+        None,
+        WrongNonce,
+        /// HS256 keyed with the public key, the classic algorithm confusion attack
+        SymmetricSignature,
+    }
+
+    /// Authorization request accepted by the mock, keyed by the code it handed out
+    struct Grant {
+        challenge: String,
+        nonce: String,
+        redirect_uri: String,
+    }
+
+    struct MockIdp {
+        issuer: String,
+        fault: Fault,
+        grants: Mutex<HashMap<String, Grant>>,
+    }
+
+    async fn discovery(State(idp): State<Arc<MockIdp>>) -> Json<Value> {
+        Json(json!({
+            "issuer": idp.issuer,
+            "authorization_endpoint": format!("{}/authorize", idp.issuer),
+            "token_endpoint": format!("{}/token", idp.issuer),
+            "jwks_uri": format!("{}/jwks", idp.issuer),
+        }))
+    }
+
+    async fn jwks() -> Json<Value> {
+        Json(json!({ "keys": [{
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": URL_SAFE_NO_PAD.encode(hex(ED25519_PUBLIC)),
+            "kid": "test",
+            "alg": "EdDSA",
+            "use": "sig",
+        }]}))
+    }
+
+    /// Logs the user in at once and redirects back with a code
+    async fn authorize(
+        State(idp): State<Arc<MockIdp>>,
+        Query(query): Query<HashMap<String, String>>,
+    ) -> Response {
+        if query["response_type"] != "code" || query["code_challenge_method"] != "S256" {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        let code = Uuid::new_v4().simple().to_string();
+        idp.grants.lock().unwrap().insert(
+            code.clone(),
+            Grant {
+                challenge: query["code_challenge"].clone(),
+                nonce: query["nonce"].clone(),
+                redirect_uri: query["redirect_uri"].clone(),
+            },
+        );
+        let mut location = Url::parse(&query["redirect_uri"]).unwrap();
+        location
+            .query_pairs_mut()
+            .append_pair("code", &code)
+            .append_pair("state", &query["state"]);
+        Redirect::to(location.as_str()).into_response()
+    }
+
+    async fn token(
+        State(idp): State<Arc<MockIdp>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Response {
+        let Some(grant) = idp.grants.lock().unwrap().remove(&form["code"]) else {
+            return (StatusCode::BAD_REQUEST, "invalid_grant").into_response();
+        };
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+        if form["grant_type"] != "authorization_code"
+            || form["client_id"] != "deuseda"
+            || form["redirect_uri"] != grant.redirect_uri
+            || challenge != grant.challenge
+        {
+            return (StatusCode::BAD_REQUEST, "invalid_grant").into_response();
+        }
+
+        let nonce = match idp.fault {
+            Fault::WrongNonce => "another-login".to_string(),
+            _ => grant.nonce,
+        };
+        let claims = json!({
+            "iss": idp.issuer,
+            "aud": "deuseda",
+            "exp": (Utc::now() + Duration::minutes(5)).timestamp(),
+            "nonce": nonce,
+            "preferred_username": "Alice@example.com",
+            "groups": ["ops"],
+        });
+        let id_token = match idp.fault {
+            Fault::SymmetricSignature => {
+                let mut header = Header::new(Algorithm::HS256);
+                header.kid = Some("test".to_string());
+                let key = EncodingKey::from_secret(&hex(ED25519_PUBLIC));
+                encode(&header, &claims, &key).unwrap()
+            }
+            _ => {
+                let mut header = Header::new(Algorithm::EdDSA);
+                header.kid = Some("test".to_string());
+                // PKCS#8 wrapping of a bare Ed25519 seed
+                let mut der = hex("302e020100300506032b657004220420");
+                der.extend(hex(ED25519_SEED));
+                encode(&header, &claims, &EncodingKey::from_ed_der(&der)).unwrap()
+            }
+        };
+        Json(json!({ "id_token": id_token, "token_type": "Bearer" })).into_response()
+    }
+
+    async fn start(fault: Fault) -> OidcProvider {
+        start_with(fault, &["EdDSA"]).await
+    }
+
+    async fn start_with(fault: Fault, algorithms: &[&str]) -> OidcProvider {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let idp = Arc::new(MockIdp {
+            issuer: issuer.clone(),
+            fault,
+            grants: Mutex::new(HashMap::new()),
+        });
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/authorize", get(authorize))
+            .route("/token", post(token))
+            .with_state(idp);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let config = OidcConfig {
+            issuer: Some(issuer),
+            allowed_groups: vec!["ops".to_string()],
+            id_token_algorithms: algorithms.iter().map(|a| a.to_string()).collect(),
+            ..OidcConfig::default()
+        };
+        OidcProvider::new(&config).unwrap().unwrap()
+    }
+
+    /// Follow the authorization URL like a browser and return the callback's code and state
+    async fn authorize_at_idp(url: &str) -> (String, String) {
+        let browser = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let response = browser.get(url).send().await.unwrap();
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        let callback: HashMap<String, String> = Url::parse(location)
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+        (callback["code"].clone(), callback["state"].clone())
+    }
+
+    #[tokio::test]
+    async fn login_flow_yields_the_mapped_username() {
+        let provider = start(Fault::None).await;
+        let url = provider.authorization_url().await.unwrap();
+        let (code, state) = authorize_at_idp(&url).await;
+
+        assert_eq!(provider.complete(&code, &state).await.unwrap(), "alice");
+    }
+
+    #[tokio::test]
+    async fn algorithms_of_several_key_families_can_be_pinned() {
+        let provider = start_with(Fault::None, &["RS256", "ES256", "EdDSA"]).await;
+        let url = provider.authorization_url().await.unwrap();
+        let (code, state) = authorize_at_idp(&url).await;
+
+        assert_eq!(provider.complete(&code, &state).await.unwrap(), "alice");
+    }
+
+    #[tokio::test]
+    async fn state_is_single_use() {
+        let provider = start(Fault::None).await;
+        let url = provider.authorization_url().await.unwrap();
+        let (code, state) = authorize_at_idp(&url).await;
+        provider.complete(&code, &state).await.unwrap();
+
+        let url = provider.authorization_url().await.unwrap();
+        let (code, _) = authorize_at_idp(&url).await;
+        assert!(provider.complete(&code, &state).await.is_err());
+        assert!(provider.complete(&code, "unknown-state").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn code_is_bound_to_its_pkce_verifier() {
+        let provider = start(Fault::None).await;
+        let first = provider.authorization_url().await.unwrap();
+        let second = provider.authorization_url().await.unwrap();
+        let (code, _) = authorize_at_idp(&first).await;
+        let (_, other_state) = authorize_at_idp(&second).await;
+
+        // The code of the first login redeemed with the verifier of the second
+        assert!(provider.complete(&code, &other_state).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn nonce_mismatch_is_rejected() {
+        let provider = start(Fault::WrongNonce).await;
+        let url = provider.authorization_url().await.unwrap();
+        let (code, state) = authorize_at_idp(&url).await;
+
+        let error = provider.complete(&code, &state).await.unwrap_err();
+        assert!(error.to_string().contains("nonce"));
+    }
+
+    #[tokio::test]
+    async fn algorithms_outside_the_pinned_list_are_rejected() {
+        let provider = start(Fault::SymmetricSignature).await;
+        let url = provider.authorization_url().await.unwrap();
+        let (code, state) = authorize_at_idp(&url).await;
+
+        let error = provider.complete(&code, &state).await.unwrap_err();
+        assert!(error.to_string().contains("not allowed"));
+    }
+
+    #[test]
+    fn shared_secret_algorithms_cannot_be_configured() {
+        let config = |algs: &[&str]| OidcConfig {
+            id_token_algorithms: algs.iter().map(|a| a.to_string()).collect(),
+            ..OidcConfig::default()
+        };
+        assert!(id_token_algorithms(&config(&["RS256", "ES256"])).is_ok());
+        assert!(id_token_algorithms(&config(&["HS256"])).is_err());
+        assert!(id_token_algorithms(&config(&["none"])).is_err());
+        assert!(id_token_algorithms(&config(&[])).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{collections::HashMap, io::Read, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use ssh2::Session;
+
+/// What a user may do. Ordered: every role can do what the roles below it can.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Watch terminals shared with them, always read-only
+    #[default]
+    Viewer,
+    /// Open terminals, manage tmux sessions, share and broadcast
+    Operator,
+    /// Everything, including the admin APIs
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Operator => "operator",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+/// Role policy file pointed to by `auth.rbac_policy_file` (or the `RBAC_POLICY_FILE` override)
+///
+/// ```json
+/// {
+///   "default_role": "operator",
+///   "users": { "alice": "admin" },
+///   "groups": { "wheel": "admin", "contractors": "viewer" },
+///   "hosts": { "bob": ["build-1:22"], "@contractors": ["sandbox.internal"] }
+/// }
+/// ```
+///
+/// `groups` are Linux groups (`id -Gn`); `hosts` restricts users (or `@group`s)
+/// to a subset of the SSH allow-list.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RbacPolicy {
+    default_role: Option<Role>,
+    users: HashMap<String, Role>,
+    groups: HashMap<String, Role>,
+    hosts: HashMap<String, Vec<String>>,
+}
+
+impl RbacPolicy {
+    /// `auth.rbac_policy_file`, or operators for everybody when unset
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut policy: Self = match path {
+            Some(path) => {
+                let raw = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read RBAC policy {}", path.display()))?;
+                serde_json::from_str(&raw)
+                    .with_context(|| format!("Invalid RBAC policy {}", path.display()))?
+            }
+            None => Self::default(),
+        };
+
+        if policy.default_role.is_none() {
+            policy.default_role = Some(Role::Operator);
+        }
+        Ok(policy)
+    }
+
+    /// Whether resolving a role needs the user's Linux groups
+    pub fn uses_groups(&self) -> bool {
+        !self.groups.is_empty() || self.hosts.keys().any(|k| k.starts_with('@'))
+    }
+
+    /// Role and host restriction for a user. An explicit user entry wins over groups;
+    /// with several matching groups the most powerful role applies.
+    /// Hosts are `None` when the user may use every allowed host.
+    pub fn resolve(&self, username: &str, groups: &[String]) -> (Role, Option<Vec<String>>) {
+        let role = self.users.get(username).copied().unwrap_or_else(|| {
+            groups
+                .iter()
+                .filter_map(|g| self.groups.get(g).copied())
+                .max()
+                .or(self.default_role)
+                .unwrap_or_default()
+        });
+
+        let keys =
+            std::iter::once(username.to_string()).chain(groups.iter().map(|g| format!("@{}", g)));
+        let hosts: Vec<String> = keys
+            .filter_map(|key| self.hosts.get(&key))
+            .flatten()
+            .cloned()
+            .collect();
+        let restricted = self.hosts.contains_key(username)
+            || groups
+                .iter()
+                .any(|g| self.hosts.contains_key(&format!("@{}", g)));
+
+        (role, restricted.then_some(hosts))
+    }
+}
+
+/// Linux groups of the authenticated user (`id -Gn`)
+pub fn linux_groups(session: &Session) -> Result<Vec<String>> {
+    let mut channel = session
+        .channel_session()
+        .map_err(|e| anyhow!("Failed to open channel: {}", e))?;
+    channel
+        .exec("id -Gn")
+        .map_err(|e| anyhow!("Failed to run id -Gn: {}", e))?;
+
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+    let _ = channel.wait_close();
+
+    Ok(output.split_whitespace().map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> RbacPolicy {
+        serde_json::from_str(
+            r#"{
+                "default_role": "viewer",
+                "users": { "alice": "admin", "mallory": "viewer" },
+                "groups": { "ops": "operator", "wheel": "admin" },
+                "hosts": { "bob": ["build-1:22"], "@contractors": ["sandbox.internal"] }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn groups(names: &[&str]) -> Vec<String> {
+        names.iter().map(|g| g.to_string()).collect()
+    }
+
+    #[test]
+    fn user_entry_wins_over_groups() {
+        let (role, _) = policy().resolve("mallory", &groups(&["wheel"]));
+        assert_eq!(role, Role::Viewer);
+        assert_eq!(policy().resolve("alice", &[]).0, Role::Admin);
+    }
+
+    #[test]
+    fn most_powerful_group_applies() {
+        let (role, _) = policy().resolve("carol", &groups(&["ops", "wheel"]));
+        assert_eq!(role, Role::Admin);
+        assert_eq!(
+            policy().resolve("dave", &groups(&["ops"])).0,
+            Role::Operator
+        );
+    }
+
+    #[test]
+    fn default_role_without_matches() {
+        assert_eq!(
+            policy().resolve("erin", &groups(&["staff"])).0,
+            Role::Viewer
+        );
+        // Without a policy file everybody is an operator
+        let open = RbacPolicy::load(None).unwrap();
+        assert_eq!(open.resolve("erin", &[]).0, Role::Operator);
+        assert!(!open.uses_groups());
+    }
+
+    #[test]
+    fn host_restrictions_combine_user_and_groups() {
+        let policy = policy();
+        assert_eq!(policy.resolve("erin", &[]).1, None);
+        assert_eq!(
+            policy.resolve("bob", &[]).1,
+            Some(vec!["build-1:22".to_string()])
+        );
+
+        let (_, hosts) = policy.resolve("bob", &groups(&["contractors"]));
+        let mut hosts = hosts.unwrap();
+        hosts.sort();
+        assert_eq!(hosts, ["build-1:22", "sandbox.internal"]);
+    }
+
+    #[test]
+    fn group_keyed_hosts_need_groups() {
+        let policy: RbacPolicy =
+            serde_json::from_str(r#"{ "hosts": { "@contractors": ["sandbox"] } }"#).unwrap();
+        assert!(policy.uses_groups());
+    }
+
+    #[test]
+    fn roles_are_ordered() {
+        assert!(Role::Admin > Role::Operator && Role::Operator > Role::Viewer);
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::ThrottleConfig;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ThrottleKey {
+    Ip(IpAddr),
+    Username(String),
+}
+
+#[derive(Debug)]
+struct ThrottleEntry {
+    /// Failed logins since the key was last cleared
+    failures: u32,
+    /// The most recent failure; `failures` decay once it is a lockout period old
+    last_failure: Option<Instant>,
+    /// Attempts still being verified; they count as failures until they are settled
+    pending: u32,
+    /// No attempts before this instant (backoff or lockout)
+    blocked_until: Option<Instant>,
+    /// Fixed one-minute window for the per-IP attempt rate
+    window_start: Instant,
+    window_attempts: u32,
+    last_seen: Instant,
+}
+
+impl ThrottleEntry {
+    fn new(now: Instant) -> Self {
+        Self {
+            failures: 0,
+            last_failure: None,
+            pending: 0,
+            blocked_until: None,
+            window_start: now,
+            window_attempts: 0,
+            last_seen: now,
+        }
+    }
+
+    /// Failures that still count
+    fn recent_failures(&self, now: Instant, lockout: Duration) -> u32 {
+        match self.last_failure {
+            Some(at) if now.duration_since(at) < lockout => self.failures,
+            _ => 0,
+        }
+    }
+}
+
+/// Brute-force protection for `/api/auth/login`, keyed by client IP and by username.
+/// Every failure backs off exponentially; `lockout_after` failures lock the key out.
+/// A success clears the username only: the IP's failures decay after `lockout_secs`
+/// without a new one, so logging in to one account cannot reset a spray from that IP.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    config: ThrottleConfig,
+    entries: Mutex<HashMap<ThrottleKey, ThrottleEntry>>,
+}
+
+/// A login attempt admitted by `LoginThrottle::reserve`. Until it is settled it counts as a
+/// failure, so concurrent attempts cannot get past the lockout while they are verified.
+/// Dropping it unsettled (the SSH server was unreachable, the client went away) gives the
+/// reservation back without counting a failure.
+#[must_use]
+pub struct LoginAttempt<'a> {
+    throttle: &'a LoginThrottle,
+    ip: IpAddr,
+    username: String,
+}
+
+impl LoginAttempt<'_> {
+    /// Correct credentials: clear the failure history of the username
+    pub fn succeeded(self) {
+        self.throttle.settle(self.ip, &self.username, false);
+    }
+
+    /// Wrong credentials: back off (or lock out) the IP and the username
+    pub fn failed(self) {
+        self.throttle.settle(self.ip, &self.username, true);
+    }
+}
+
+impl Drop for LoginAttempt<'_> {
+    fn drop(&mut self) {
+        self.throttle.release(self.ip, &self.username);
+    }
+}
+
+impl LoginThrottle {
+    pub fn new(config: &ThrottleConfig) -> Self {
+        Self {
+            config: config.clone(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Admit a login attempt. `Err` carries how long the client has to wait.
+    pub fn reserve(&self, ip: IpAddr, username: &str) -> Result<LoginAttempt<'_>, Duration> {
+        let now = Instant::now();
+        let lockout = Duration::from_secs(self.config.lockout_secs);
+        let mut entries = self.entries.lock().unwrap();
+        let keys = [ThrottleKey::Ip(ip), username_key(username)];
+
+        let blocked = keys
+            .iter()
+            .filter_map(|key| entries.get(key)?.blocked_until)
+            .filter(|until| *until > now)
+            .max();
+        if let Some(until) = blocked {
+            return Err(until - now);
+        }
+        // Attempts in flight would reach the lockout if they all failed
+        let saturated = keys.iter().any(|key| {
+            entries.get(key).is_some_and(|e| {
+                e.recent_failures(now, lockout) + e.pending >= self.config.lockout_after
+            })
+        });
+        if saturated {
+            return Err(Duration::from_secs(self.config.backoff_base_secs.max(1)));
+        }
+
+        let entry = entries
+            .entry(ThrottleKey::Ip(ip))
+            .or_insert_with(|| ThrottleEntry::new(now));
+        if now.duration_since(entry.window_start) >= Duration::from_secs(60) {
+            entry.window_start = now;
+            entry.window_attempts = 0;
+        }
+        entry.window_attempts += 1;
+        if entry.window_attempts > self.config.attempts_per_minute {
+            return Err(entry.window_start + Duration::from_secs(60) - now);
+        }
+
+        for key in keys {
+            let entry = entries
+                .entry(key)
+                .or_insert_with(|| ThrottleEntry::new(now));
+            entry.pending += 1;
+            entry.last_seen = now;
+        }
+        Ok(LoginAttempt {
+            throttle: self,
+            ip,
+            username: username.to_string(),
+        })
+    }
+
+    fn settle(&self, ip: IpAddr, username: &str, failed: bool) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        // Forget keys that have been quiet for longer than a lockout
+        let lockout = Duration::from_secs(self.config.lockout_secs);
+        entries.retain(|_, e| e.pending > 0 || now.duration_since(e.last_seen) < lockout);
+
+        for key in [ThrottleKey::Ip(ip), username_key(username)] {
+            let entry = entries
+                .entry(key.clone())
+                .or_insert_with(|| ThrottleEntry::new(now));
+            entry.last_seen = now;
+            if !failed {
+                if matches!(key, ThrottleKey::Username(_)) {
+                    entry.failures = 0;
+                    entry.last_failure = None;
+                    entry.blocked_until = None;
+                }
+                continue;
+            }
+
+            entry.failures = entry.recent_failures(now, lockout) + 1;
+            entry.last_failure = Some(now);
+            if entry.failures >= self.config.lockout_after {
+                entry.blocked_until = Some(now + lockout);
+                // Only report the transition into the lockout
+                if entry.failures == self.config.lockout_after {
+                    tracing::warn!(
+                        target: "deuseda::audit",
+                        event = "login_lockout",
+                        ip = %ip,
+                        username = %username,
+                        "Login locked out for {:?} after {} failed attempts ({:?})",
+                        lockout,
+                        entry.failures,
+                        key
+                    );
+                }
+            } else {
+                let backoff = Duration::from_secs(self.config.backoff_base_secs)
+                    .checked_mul(2u32.saturating_pow(entry.failures - 1))
+                    .unwrap_or(lockout);
+                entry.blocked_until = Some(now + backoff.min(lockout));
+            }
+        }
+    }
+
+    /// Give back the reservation of an attempt, settled or not
+    fn release(&self, ip: IpAddr, username: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        for key in [ThrottleKey::Ip(ip), username_key(username)] {
+            if let Some(entry) = entries.get_mut(&key) {
+                entry.pending = entry.pending.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Usernames are case-insensitive for throttling, so `Root` and `root` share a counter
+fn username_key(username: &str) -> ThrottleKey {
+    ThrottleKey::Username(username.trim().to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 2));
+    const THIRD_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 3));
+
+    /// No backoff between failures, so only the lockout is in play
+    fn throttle(lockout_after: u32) -> LoginThrottle {
+        LoginThrottle::new(&ThrottleConfig {
+            attempts_per_minute: 100,
+            lockout_after,
+            lockout_secs: 60,
+            backoff_base_secs: 0,
+        })
+    }
+
+    #[test]
+    fn locks_out_after_consecutive_failures() {
+        let throttle = throttle(3);
+        for _ in 0..3 {
+            throttle.reserve(IP, "alice").unwrap().failed();
+        }
+
+        let wait = throttle.reserve(IP, "alice").err().unwrap();
+        assert!(wait > Duration::from_secs(50));
+        // Both keys are locked: another IP for the user, another user from the IP
+        assert!(throttle.reserve(OTHER_IP, "alice").is_err());
+        assert!(throttle.reserve(IP, "bob").is_err());
+    }
+
+    #[test]
+    fn concurrent_attempts_cannot_exceed_the_lockout() {
+        let throttle = throttle(3);
+        let in_flight: Vec<_> = (0..3)
+            .map(|_| throttle.reserve(IP, "alice").unwrap())
+            .collect();
+        assert!(throttle.reserve(IP, "alice").is_err());
+
+        for attempt in in_flight {
+            attempt.failed();
+        }
+        assert!(throttle.reserve(OTHER_IP, "alice").is_err());
+    }
+
+    #[test]
+    fn success_gives_the_reservation_back_and_clears_the_username() {
+        let throttle = throttle(3);
+        throttle.reserve(IP, "alice").unwrap().failed();
+        throttle.reserve(IP, "alice").unwrap().failed();
+        throttle.reserve(OTHER_IP, "alice").unwrap().succeeded();
+
+        for _ in 0..2 {
+            throttle.reserve(THIRD_IP, "alice").unwrap().failed();
+        }
+        assert!(throttle.reserve(THIRD_IP, "alice").is_ok());
+    }
+
+    #[test]
+    fn success_does_not_clear_ip_lockout() {
+        let throttle = throttle(3);
+        throttle.reserve(IP, "alice").unwrap().failed();
+        throttle.reserve(IP, "bob").unwrap().failed();
+        // The attacker's own account, between guesses
+        throttle.reserve(IP, "mallory").unwrap().succeeded();
+        throttle.reserve(IP, "carol").unwrap().failed();
+
+        assert!(throttle.reserve(IP, "dave").is_err());
+        assert!(throttle.reserve(IP, "mallory").is_err());
+    }
+
+    #[test]
+    fn ip_failures_decay_after_a_quiet_lockout_period() {
+        let throttle = throttle(3);
+        throttle.reserve(IP, "alice").unwrap().failed();
+        throttle.reserve(IP, "bob").unwrap().failed();
+        for entry in throttle.entries.lock().unwrap().values_mut() {
+            entry.last_failure = entry.last_failure.map(|at| at - Duration::from_secs(60));
+        }
+
+        throttle.reserve(IP, "carol").unwrap().failed();
+        assert!(throttle.reserve(IP, "dave").is_ok());
+    }
+
+    #[test]
+    fn unsettled_attempts_do_not_count() {
+        let throttle = throttle(2);
+        for _ in 0..10 {
+            // e.g. the SSH server was unreachable
+            drop(throttle.reserve(IP, "alice").unwrap());
+        }
+        assert!(throttle.reserve(IP, "alice").is_ok());
+    }
+
+    #[test]
+    fn failures_back_off_exponentially() {
+        let throttle = LoginThrottle::new(&ThrottleConfig {
+            backoff_base_secs: 10,
+            ..ThrottleConfig::default()
+        });
+        throttle.reserve(IP, "alice").unwrap().failed();
+        let first = throttle.reserve(IP, "alice").err().unwrap();
+        assert!(first > Duration::from_secs(9) && first <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn usernames_share_a_counter_regardless_of_case() {
+        let throttle = throttle(2);
+        throttle.reserve(IP, "Root").unwrap().failed();
+        throttle.reserve(OTHER_IP, " root").unwrap().failed();
+        assert!(throttle.reserve(OTHER_IP, "ROOT").is_err());
+    }
+
+    #[test]
+    fn limits_attempts_per_minute_per_ip() {
+        let throttle = LoginThrottle::new(&ThrottleConfig {
+            attempts_per_minute: 3,
+            ..ThrottleConfig::default()
+        });
+        for user in ["a", "b", "c"] {
+            throttle.reserve(IP, user).unwrap().succeeded();
+        }
+        assert!(throttle.reserve(IP, "d").is_err());
+        assert!(throttle.reserve(OTHER_IP, "d").is_ok());
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use uuid::Uuid;
+
+use crate::middleware::auth::Claims;
+
+/// The one WebSocket a ticket opens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TicketScope {
+    /// `/ws/terminal` for one session on one host
+    Terminal {
+        session_id: String,
+        host: Option<String>,
+    },
+    /// `/ws/terminal/mux`
+    Mux,
+    /// `/ws/terminal/share` for one share link
+    Share { share: String },
+    /// `/ws/broadcast` for one group
+    Broadcast { group_id: Uuid },
+}
+
+/// Connection ticket waiting to be redeemed by a WebSocket upgrade
+struct Ticket {
+    claims: Claims,
+    scope: TicketScope,
+    expires_at: Instant,
+}
+
+/// Single-use, seconds-lived tickets that stand in for the access token when
+/// opening a WebSocket, so the JWT never appears in a URL
+#[derive(Default)]
+pub struct TicketStore {
+    tickets: Mutex<HashMap<String, Ticket>>,
+}
+
+impl TicketStore {
+    /// Issue a ticket for one socket, valid for `ttl`
+    pub fn issue(&self, claims: Claims, scope: TicketScope, ttl: Duration) -> String {
+        let ticket = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let now = Instant::now();
+
+        let mut tickets = self.tickets.lock().unwrap();
+        tickets.retain(|_, t| t.expires_at > now);
+        tickets.insert(
+            ticket.clone(),
+            Ticket {
+                claims,
+                scope,
+                expires_at: now + ttl,
+            },
+        );
+
+        ticket
+    }
+
+    /// Consume a ticket. It is gone after the first attempt, even when the
+    /// scope does not match, so it can never be replayed.
+    pub fn redeem(&self, ticket: &str, scope: &TicketScope) -> Option<Claims> {
+        let ticket = self.take(ticket)?;
+        if ticket.scope != *scope {
+            tracing::warn!(
+                "Ticket of {} for {:?} used for {:?}",
+                ticket.claims.username(),
+                ticket.scope,
+                scope
+            );
+            return None;
+        }
+
+        Some(ticket.claims)
+    }
+
+    /// Consume a share ticket and return the share link it was issued for, so the
+    /// share token never has to appear in the socket URL
+    pub fn redeem_share(&self, ticket: &str) -> Option<(Claims, String)> {
+        let ticket = self.take(ticket)?;
+        match ticket.scope {
+            TicketScope::Share { share } => Some((ticket.claims, share)),
+            scope => {
+                tracing::warn!(
+                    "Ticket of {} for {:?} used for a shared terminal",
+                    ticket.claims.username(),
+                    scope
+                );
+                None
+            }
+        }
+    }
+
+    /// Remove a ticket, returning it only while it is still valid
+    fn take(&self, ticket: &str) -> Option<Ticket> {
+        let ticket = self.tickets.lock().unwrap().remove(ticket)?;
+        (ticket.expires_at > Instant::now()).then_some(ticket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::rbac::Role;
+
+    const TTL: Duration = Duration::from_secs(30);
+
+    fn claims() -> Claims {
+        Claims {
+            sub: "alice".into(),
+            exp: 0,
+            iat: 0,
+            jti: Uuid::new_v4().to_string(),
+            fam: Uuid::new_v4().to_string(),
+            sso: false,
+            role: Role::Operator,
+            hosts: None,
+        }
+    }
+
+    fn terminal(session_id: &str, host: Option<&str>) -> TicketScope {
+        TicketScope::Terminal {
+            session_id: session_id.into(),
+            host: host.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn redeems_once() {
+        let store = TicketStore::default();
+        let ticket = store.issue(claims(), terminal("main", None), TTL);
+
+        let claims = store.redeem(&ticket, &terminal("main", None)).unwrap();
+        assert_eq!(claims.username(), "alice");
+        assert!(store.redeem(&ticket, &terminal("main", None)).is_none());
+    }
+
+    #[test]
+    fn scope_mismatch_burns_the_ticket() {
+        let store = TicketStore::default();
+        let ticket = store.issue(claims(), terminal("main", Some("db1")), TTL);
+
+        assert!(store
+            .redeem(&ticket, &terminal("main", Some("db2")))
+            .is_none());
+        assert!(store
+            .redeem(&ticket, &terminal("main", Some("db1")))
+            .is_none());
+    }
+
+    #[test]
+    fn tickets_only_open_their_own_socket() {
+        let store = TicketStore::default();
+        let group_id = Uuid::new_v4();
+        let ticket = store.issue(claims(), TicketScope::Broadcast { group_id }, TTL);
+        assert!(store.redeem(&ticket, &TicketScope::Mux).is_none());
+
+        let ticket = store.issue(claims(), TicketScope::Broadcast { group_id }, TTL);
+        let other = TicketScope::Broadcast {
+            group_id: Uuid::new_v4(),
+        };
+        assert!(store.redeem(&ticket, &other).is_none());
+
+        let ticket = store.issue(claims(), TicketScope::Mux, TTL);
+        assert!(store.redeem(&ticket, &TicketScope::Mux).is_some());
+    }
+
+    #[test]
+    fn share_tickets_name_their_share_link() {
+        let store = TicketStore::default();
+        let share = TicketScope::Share {
+            share: "link-token".into(),
+        };
+        let ticket = store.issue(claims(), share, TTL);
+
+        let (viewer, share) = store.redeem_share(&ticket).unwrap();
+        assert_eq!((viewer.username(), share.as_str()), ("alice", "link-token"));
+        assert!(store.redeem_share(&ticket).is_none());
+
+        // Other tickets do not open a shared terminal, and are burnt trying
+        let ticket = store.issue(claims(), TicketScope::Mux, TTL);
+        assert!(store.redeem_share(&ticket).is_none());
+        assert!(store.redeem(&ticket, &TicketScope::Mux).is_none());
+    }
+
+    #[test]
+    fn expired_and_unknown_tickets_are_rejected() {
+        let store = TicketStore::default();
+        let ticket = store.issue(claims(), TicketScope::Mux, Duration::ZERO);
+
+        assert!(store.redeem(&ticket, &TicketScope::Mux).is_none());
+        assert!(store.redeem("made-up", &TicketScope::Mux).is_none());
+    }
+}
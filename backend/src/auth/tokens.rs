@@ -0,0 +1,388 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use subtle::ConstantTimeEq;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+use super::{keys::KeyRing, rbac::Role};
+use crate::{config::AuthConfig, middleware::auth::Claims};
+
+/// Access + refresh token pair handed out at login and on every refresh
+pub struct IssuedTokens {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+}
+
+/// Who a token pair is issued for; kept with the refresh family so refreshed tokens keep it
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub username: String,
+    /// Logged in through the identity provider
+    pub sso: bool,
+    pub role: Role,
+    /// Hosts the user is restricted to, `None` for every allowed host
+    pub hosts: Option<Vec<String>>,
+}
+
+/// All refresh tokens descending from one login; revoking it logs that login out everywhere
+#[derive(Debug)]
+struct RefreshFamily {
+    identity: Identity,
+    /// Secret part of the only refresh token that may be used next
+    current: String,
+    expires_at: DateTime<Utc>,
+    revoked: bool,
+}
+
+/// Server-side token state: rotating refresh tokens and the access token revocation list.
+/// Kept in memory, so a restart logs everybody out.
+#[derive(Debug)]
+pub struct TokenStore {
+    access_ttl: Duration,
+    refresh_ttl: Duration,
+    keys: KeyRing,
+    families: RwLock<HashMap<Uuid, RefreshFamily>>,
+    /// Revoked `jti`s with the expiry of their token
+    revoked_jti: RwLock<HashMap<Uuid, usize>>,
+    /// Families revoked at logout; open terminals of the family listen here
+    revocations: broadcast::Sender<Uuid>,
+}
+
+impl TokenStore {
+    pub fn new(config: &AuthConfig) -> Result<Self> {
+        let (revocations, _) = broadcast::channel(64);
+
+        Ok(Self {
+            access_ttl: Duration::seconds(config.access_token_ttl),
+            refresh_ttl: Duration::seconds(config.refresh_token_ttl),
+            keys: KeyRing::load(config)?,
+            families: RwLock::new(HashMap::new()),
+            revoked_jti: RwLock::new(HashMap::new()),
+            revocations,
+        })
+    }
+
+    /// Signing keys, for verification and the JWKS endpoint
+    pub fn keys(&self) -> &KeyRing {
+        &self.keys
+    }
+
+    /// Start a new refresh family for a freshly authenticated user
+    pub fn issue(&self, identity: &Identity) -> Result<IssuedTokens> {
+        let family = Uuid::new_v4();
+        let secret = Uuid::new_v4().simple().to_string();
+
+        let mut families = self.families.write().unwrap();
+        let now = Utc::now();
+        families.retain(|_, f| f.expires_at > now);
+        families.insert(
+            family,
+            RefreshFamily {
+                identity: identity.clone(),
+                current: secret.clone(),
+                expires_at: now + self.refresh_ttl,
+                revoked: false,
+            },
+        );
+        drop(families);
+
+        self.tokens_for(identity, family, &secret)
+    }
+
+    /// Exchange a refresh token for a new pair. Presenting an already rotated
+    /// token is treated as theft and revokes the whole family.
+    pub fn refresh(&self, refresh_token: &str) -> Result<(Identity, IssuedTokens)> {
+        let (family, secret) =
+            parse_refresh_token(refresh_token).ok_or_else(|| anyhow!("Malformed refresh token"))?;
+
+        let mut families = self.families.write().unwrap();
+        let entry = families
+            .get_mut(&family)
+            .filter(|f| !f.revoked && f.expires_at > Utc::now())
+            .ok_or_else(|| anyhow!("Refresh token expired or revoked"))?;
+
+        if !bool::from(entry.current.as_bytes().ct_eq(secret.as_bytes())) {
+            entry.revoked = true;
+            let username = entry.identity.username.clone();
+            drop(families);
+            tracing::warn!(
+                "Refresh token reuse detected for user {}, revoking session family",
+                username
+            );
+            let _ = self.revocations.send(family);
+            return Err(anyhow!("Refresh token was already used"));
+        }
+
+        let next = Uuid::new_v4().simple().to_string();
+        entry.current = next.clone();
+        entry.expires_at = Utc::now() + self.refresh_ttl;
+        let identity = entry.identity.clone();
+        drop(families);
+
+        let tokens = self.tokens_for(&identity, family, &next)?;
+        Ok((identity, tokens))
+    }
+
+    /// Log out: revoke the access token and its refresh family, closing open terminals
+    pub fn revoke(&self, claims: &Claims) {
+        if let Ok(jti) = claims.jti.parse::<Uuid>() {
+            let now = Utc::now().timestamp() as usize;
+            let mut revoked = self.revoked_jti.write().unwrap();
+            revoked.retain(|_, exp| *exp > now);
+            revoked.insert(jti, claims.exp);
+        }
+
+        if let Some(family) = claims.family() {
+            if let Some(entry) = self.families.write().unwrap().get_mut(&family) {
+                entry.revoked = true;
+            }
+            let _ = self.revocations.send(family);
+        }
+    }
+
+    /// Whether an otherwise valid access token has been revoked
+    pub fn is_revoked(&self, claims: &Claims) -> bool {
+        let jti_revoked = claims
+            .jti
+            .parse::<Uuid>()
+            .map(|jti| self.revoked_jti.read().unwrap().contains_key(&jti))
+            .unwrap_or(true);
+
+        let family_revoked = claims
+            .family()
+            .map(|family| self.family_revoked(family))
+            .unwrap_or(true);
+
+        jti_revoked || family_revoked
+    }
+
+    /// Whether a refresh family has been revoked or is gone altogether
+    fn family_revoked(&self, family: Uuid) -> bool {
+        self.families
+            .read()
+            .unwrap()
+            .get(&family)
+            .map(|f| f.revoked)
+            .unwrap_or(true)
+    }
+
+    /// Users with a login whose refresh token has not expired yet, logged out or not
+    pub fn known_users(&self) -> Vec<String> {
+        let now = Utc::now();
+        let mut users: Vec<String> = self
+            .families
+            .read()
+            .unwrap()
+            .values()
+            .filter(|f| f.expires_at > now)
+            .map(|f| f.identity.username.clone())
+            .collect();
+        users.sort();
+        users.dedup();
+        users
+    }
+
+    /// Watch for the revocation of the family an open terminal was authenticated with
+    pub fn watch_revocation(self: &Arc<Self>, family: Option<Uuid>) -> RevocationWatch {
+        RevocationWatch {
+            store: self.clone(),
+            family,
+            revocations: self.revocations.subscribe(),
+        }
+    }
+
+    fn tokens_for(&self, identity: &Identity, family: Uuid, secret: &str) -> Result<IssuedTokens> {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: identity.username.clone(), // username directly in JWT
+            exp: (now + self.access_ttl).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            jti: Uuid::new_v4().to_string(),
+            fam: family.to_string(),
+            sso: identity.sso,
+            role: identity.role,
+            hosts: identity.hosts.clone(),
+        };
+
+        let access_token = self.keys.sign(&claims)?;
+
+        Ok(IssuedTokens {
+            access_token,
+            refresh_token: format!("{}.{}", family.simple(), secret),
+            expires_in: self.access_ttl.num_seconds(),
+        })
+    }
+}
+
+/// Revocations seen by one open terminal
+pub struct RevocationWatch {
+    store: Arc<TokenStore>,
+    family: Option<Uuid>,
+    revocations: broadcast::Receiver<Uuid>,
+}
+
+impl RevocationWatch {
+    /// Resolves once the watched family is revoked; never for a token without a family.
+    /// Cancel safe, so it can be a `select!` branch.
+    pub async fn revoked(&mut self) {
+        let Some(family) = self.family else {
+            return std::future::pending().await;
+        };
+        loop {
+            match self.revocations.recv().await {
+                Ok(revoked) if revoked == family => return,
+                Ok(_) => {}
+                // Missed revocations may include ours, so ask the store directly
+                Err(RecvError::Lagged(_)) => {
+                    if self.store.family_revoked(family) {
+                        return;
+                    }
+                }
+                Err(RecvError::Closed) => return std::future::pending().await,
+            }
+        }
+    }
+}
+
+/// Refresh tokens look like `<family>.<secret>`
+fn parse_refresh_token(token: &str) -> Option<(Uuid, &str)> {
+    let (family, secret) = token.split_once('.')?;
+    Some((family.parse().ok()?, secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> Arc<TokenStore> {
+        let config = AuthConfig {
+            jwt_secret: Some("test-secret-with-enough-length".parse().unwrap()),
+            ..AuthConfig::default()
+        };
+        Arc::new(TokenStore::new(&config).unwrap())
+    }
+
+    fn identity() -> Identity {
+        Identity {
+            username: "alice".into(),
+            sso: false,
+            role: Role::Operator,
+            hosts: None,
+        }
+    }
+
+    fn claims(store: &TokenStore, tokens: &IssuedTokens) -> Claims {
+        store.keys().verify(&tokens.access_token).unwrap()
+    }
+
+    #[test]
+    fn refresh_rotates_the_token() {
+        let store = store();
+        let first = store.issue(&identity()).unwrap();
+
+        let (identity, second) = store.refresh(&first.refresh_token).unwrap();
+        assert_eq!(identity.username, "alice");
+        assert_ne!(first.refresh_token, second.refresh_token);
+        assert!(!store.is_revoked(&claims(&store, &second)));
+
+        // The rotated token keeps working for its successor
+        assert!(store.refresh(&second.refresh_token).is_ok());
+    }
+
+    #[test]
+    fn reusing_a_rotated_token_revokes_the_family() {
+        let store = store();
+        let first = store.issue(&identity()).unwrap();
+        let (_, second) = store.refresh(&first.refresh_token).unwrap();
+        let mut revocations = store.revocations.subscribe();
+
+        assert!(store.refresh(&first.refresh_token).is_err());
+        let family = claims(&store, &second).family().unwrap();
+        assert_eq!(revocations.try_recv().unwrap(), family);
+
+        // Neither the thief nor the legitimate holder can continue
+        assert!(store.refresh(&second.refresh_token).is_err());
+        assert!(store.is_revoked(&claims(&store, &second)));
+    }
+
+    #[test]
+    fn other_families_are_untouched_by_reuse() {
+        let store = store();
+        let mine = store.issue(&identity()).unwrap();
+        let other = store.issue(&identity()).unwrap();
+        store.refresh(&mine.refresh_token).unwrap();
+
+        assert!(store.refresh(&mine.refresh_token).is_err());
+        assert!(!store.is_revoked(&claims(&store, &other)));
+        assert!(store.refresh(&other.refresh_token).is_ok());
+    }
+
+    #[test]
+    fn malformed_refresh_tokens_are_rejected() {
+        let store = store();
+        assert!(store.refresh("not-a-token").is_err());
+        assert!(store
+            .refresh(&format!("{}.secret", Uuid::new_v4().simple()))
+            .is_err());
+    }
+
+    #[test]
+    fn logout_revokes_access_and_refresh() {
+        let store = store();
+        let tokens = store.issue(&identity()).unwrap();
+        let claims = claims(&store, &tokens);
+
+        store.revoke(&claims);
+        assert!(store.is_revoked(&claims));
+        assert!(store.refresh(&tokens.refresh_token).is_err());
+    }
+
+    #[tokio::test]
+    async fn watch_resolves_on_revocation_of_its_family() {
+        let store = store();
+        let tokens = store.issue(&identity()).unwrap();
+        let claims = claims(&store, &tokens);
+        let mut watch = store.watch_revocation(claims.family());
+
+        store.revoke(&claims);
+        tokio::time::timeout(std::time::Duration::from_secs(1), watch.revoked())
+            .await
+            .expect("revocation seen");
+    }
+
+    #[tokio::test]
+    async fn watch_rechecks_the_store_after_lagging() {
+        let store = store();
+        let tokens = store.issue(&identity()).unwrap();
+        let claims = claims(&store, &tokens);
+        let mut watch = store.watch_revocation(claims.family());
+
+        // Our revocation is pushed out of the channel by later ones
+        store.revoke(&claims);
+        for _ in 0..100 {
+            let _ = store.revocations.send(Uuid::new_v4());
+        }
+        tokio::time::timeout(std::time::Duration::from_secs(1), watch.revoked())
+            .await
+            .expect("revocation seen after lag");
+    }
+
+    #[tokio::test]
+    async fn watch_ignores_other_families() {
+        let store = store();
+        let mine = store.issue(&identity()).unwrap();
+        let other = store.issue(&identity()).unwrap();
+        let mut watch = store.watch_revocation(claims(&store, &mine).family());
+
+        store.revoke(&claims(&store, &other));
+        let waited =
+            tokio::time::timeout(std::time::Duration::from_millis(50), watch.revoked()).await;
+        assert!(waited.is_err());
+    }
+}
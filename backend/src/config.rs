@@ -0,0 +1,834 @@
+use std::{
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use ipnet::IpNet;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+use crate::{server::ListenAddr, ssh::SshTarget, telemetry::LogFormat, terminal::idle::IdleAction};
+
+/// Settings loaded once at startup from `DEUSEDA_CONFIG` (default `deuseda.toml`,
+/// optional) with environment variables taking precedence over the file.
+/// See `deuseda.example.toml` for every key and the variable that overrides it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub ssh: SshConfig,
+    pub auth: AuthConfig,
+    pub terminal: TerminalConfig,
+    pub reaper: ReaperConfig,
+    pub metrics: MetricsConfig,
+    pub health: HealthConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+/// Secret setting (JWT secret, OIDC client secret, metrics token). Its `Debug` output is
+/// `[REDACTED]`, so the config can be logged as a whole.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Redacted(String);
+
+impl Redacted {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl FromStr for Redacted {
+    type Err = std::convert::Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(Self(value.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// IP address or host name to listen on; `::` accepts IPv4 and IPv6
+    pub host: String,
+    pub port: u16,
+    /// Listeners such as `127.0.0.1:8080`, `[::1]:8080` or `unix:/run/deuseda/http.sock`,
+    /// replacing `host`/`port` when set
+    pub listen: Vec<String>,
+    /// Octal permissions of Unix sockets, e.g. `660` for the reverse proxy's group
+    pub unix_socket_mode: String,
+    /// Browser origins allowed to call the API and open terminal WebSockets (`*` = any)
+    pub cors_allowed_origins: Vec<String>,
+    /// Take the client IP from `X-Forwarded-For` (behind a reverse proxy)
+    pub trust_proxy_headers: bool,
+    /// Addresses or CIDR ranges of the reverse proxies whose `X-Forwarded-For` is believed
+    pub trusted_proxies: Vec<String>,
+    /// Seconds open terminals get to detach after `SIGTERM` before they are closed
+    pub shutdown_grace: u64,
+    pub tls: TlsConfig,
+}
+
+/// Built-in HTTPS/WSS on the TCP listeners; off unless `cert_file` is set
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain, reloaded when the file changes
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    /// Require client certificates signed by these CAs (mTLS)
+    pub client_ca_file: Option<PathBuf>,
+    /// Plain HTTP port that redirects to HTTPS
+    pub http_redirect_port: Option<u16>,
+}
+
+impl TlsConfig {
+    pub fn enabled(&self) -> bool {
+        self.cert_file.is_some()
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "0.0.0.0".to_string(),
+            port: 8080,
+            listen: Vec::new(),
+            unix_socket_mode: "660".to_string(),
+            cors_allowed_origins: vec!["http://localhost:5173".to_string()],
+            trust_proxy_headers: false,
+            trusted_proxies: vec!["127.0.0.1".to_string(), "::1".to_string()],
+            shutdown_grace: 20,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SshConfig {
+    /// Default SSH server, also used to verify passwords at login
+    pub host: String,
+    pub port: u16,
+    /// Further `host` / `host:port` entries users may connect to
+    pub hosts: Vec<String>,
+    /// Private keys of SSO users, one file per Linux username
+    pub user_key_dir: Option<PathBuf>,
+    pub ca: CaConfig,
+}
+
+impl ServerConfig {
+    /// Addresses to bind: `listen`, or `host`/`port`
+    pub fn listen_addrs(&self) -> Result<Vec<ListenAddr>> {
+        if self.listen.is_empty() {
+            return Ok(vec![ListenAddr::Tcp {
+                host: self.host.clone(),
+                port: self.port,
+            }]);
+        }
+
+        self.listen
+            .iter()
+            .map(|value| {
+                ListenAddr::parse(value)
+                    .ok_or_else(|| anyhow!("server.listen: invalid address {:?}", value))
+            })
+            .collect()
+    }
+
+    pub fn unix_socket_mode(&self) -> Result<u32> {
+        u32::from_str_radix(self.unix_socket_mode.trim(), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .ok_or_else(|| {
+                anyhow!(
+                    "server.unix_socket_mode: {:?} is not an octal mode",
+                    self.unix_socket_mode
+                )
+            })
+    }
+
+    /// `trusted_proxies` as networks; a bare address is a network of one
+    pub fn trusted_proxies(&self) -> Result<Vec<IpNet>> {
+        self.trusted_proxies
+            .iter()
+            .map(|value| {
+                let value = value.trim();
+                value
+                    .parse::<IpNet>()
+                    .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| anyhow!("server.trusted_proxies: invalid address {:?}", value))
+            })
+            .collect()
+    }
+}
+
+impl Default for SshConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 22,
+            hosts: Vec::new(),
+            user_key_dir: None,
+            ca: CaConfig::default(),
+        }
+    }
+}
+
+impl SshConfig {
+    pub fn default_target(&self) -> SshTarget {
+        // `ssh.host` has no port, but an IPv6 address may still come in brackets
+        let host = self.host.trim();
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        SshTarget {
+            host: host.to_string(),
+            port: self.port,
+        }
+    }
+
+    /// All hosts users may connect to: `hosts` plus the default host
+    pub fn allowed_targets(&self) -> Vec<SshTarget> {
+        let mut targets = vec![self.default_target()];
+        for target in self.hosts.iter().filter_map(|h| SshTarget::parse(h)) {
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        targets
+    }
+
+    /// Resolve a host requested by the client against the allow-list.
+    /// `None` selects the default host.
+    pub fn resolve(&self, requested: Option<&str>) -> Option<SshTarget> {
+        match requested {
+            None => Some(self.default_target()),
+            Some(value) => {
+                let requested = SshTarget::parse(value)?;
+                self.allowed_targets()
+                    .into_iter()
+                    .find(|target| *target == requested)
+            }
+        }
+    }
+}
+
+/// Embedded SSH certificate authority and the policy of the certificates it mints
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaConfig {
+    /// Unencrypted OpenSSH private key; the CA is off without it
+    pub key_file: Option<PathBuf>,
+    pub cert_ttl: u64,
+    /// `source-address` critical option (comma separated CIDRs), usually the backend's egress
+    pub source_address: Option<String>,
+    /// `force-command` critical option
+    pub force_command: Option<String>,
+    /// Extensions such as `permit-pty`; the terminal needs at least that one
+    pub extensions: Vec<String>,
+    /// Principals granted besides the Linux username
+    pub extra_principals: Vec<String>,
+}
+
+impl Default for CaConfig {
+    fn default() -> Self {
+        Self {
+            key_file: None,
+            cert_ttl: 5 * 60,
+            source_address: None,
+            force_command: None,
+            extensions: vec!["permit-pty".to_string()],
+            extra_principals: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// HS256 secret, used unless a key ring file is configured
+    pub jwt_secret: Option<Redacted>,
+    pub jwt_keyring_file: Option<PathBuf>,
+    /// Seconds
+    pub access_token_ttl: i64,
+    /// Seconds
+    pub refresh_token_ttl: i64,
+    pub rbac_policy_file: Option<PathBuf>,
+    pub throttle: ThrottleConfig,
+    /// OpenID Connect login; off unless `issuer` is set
+    pub oidc: OidcConfig,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: None,
+            jwt_keyring_file: None,
+            access_token_ttl: 15 * 60,
+            refresh_token_ttl: 7 * 24 * 60 * 60,
+            rbac_policy_file: None,
+            throttle: ThrottleConfig::default(),
+            oidc: OidcConfig::default(),
+        }
+    }
+}
+
+/// Login throttling
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThrottleConfig {
+    /// Login attempts one client IP may make per minute
+    pub attempts_per_minute: u32,
+    /// Consecutive failures that lock an IP or username out
+    pub lockout_after: u32,
+    pub lockout_secs: u64,
+    /// First backoff after a failure; doubles with every further failure
+    pub backoff_base_secs: u64,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            attempts_per_minute: 20,
+            lockout_after: 5,
+            lockout_secs: 15 * 60,
+            backoff_base_secs: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    pub issuer: Option<String>,
+    pub client_id: String,
+    pub client_secret: Option<Redacted>,
+    /// Our `/api/auth/oidc/callback` as registered at the identity provider
+    pub redirect_uri: String,
+    pub scopes: String,
+    /// Claim holding the Linux username
+    pub username_claim: String,
+    /// Turn `alice@example.com` into `alice`
+    pub username_strip_domain: bool,
+    pub groups_claim: String,
+    /// Users must be in one of these groups; empty allows everybody
+    pub allowed_groups: Vec<String>,
+    /// Frontend page that receives the tokens (in the URL fragment) after login
+    pub post_login_url: String,
+    /// Signature algorithms accepted on ID tokens (`RS256`, `ES256`, `EdDSA`, ...)
+    pub id_token_algorithms: Vec<String>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        Self {
+            issuer: None,
+            client_id: "deuseda".to_string(),
+            client_secret: None,
+            redirect_uri: "http://localhost:8080/api/auth/oidc/callback".to_string(),
+            scopes: "openid profile email groups".to_string(),
+            username_claim: "preferred_username".to_string(),
+            username_strip_domain: true,
+            groups_claim: "groups".to_string(),
+            allowed_groups: Vec::new(),
+            post_login_url: "http://localhost:5173/".to_string(),
+            id_token_algorithms: vec!["RS256".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerminalConfig {
+    pub broadcast_enabled: bool,
+    /// Lifetime of one-time `/ws/terminal` connection tickets in seconds
+    pub ticket_ttl: u64,
+    /// Maximum lifetime of share links in seconds
+    pub share_max_ttl: u64,
+    /// Open terminals per user; `None` is unlimited
+    pub max_sessions_per_user: Option<usize>,
+    /// Open terminals per host across all users
+    pub max_sessions_per_host: Option<usize>,
+    pub max_tmux_sessions_per_user: Option<usize>,
+    /// Broadcast groups one user may have at a time
+    pub max_broadcast_groups_per_user: usize,
+    pub idle: IdleConfig,
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        Self {
+            broadcast_enabled: true,
+            ticket_ttl: 30,
+            share_max_ttl: 24 * 60 * 60,
+            max_sessions_per_user: None,
+            max_sessions_per_host: None,
+            max_tmux_sessions_per_user: None,
+            max_broadcast_groups_per_user: 10,
+            idle: IdleConfig::default(),
+        }
+    }
+}
+
+/// Inactivity and lifetime limits of terminal channels, in seconds
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdleConfig {
+    /// Without input or output; `None` never times out
+    pub timeout: Option<u64>,
+    /// Notice before disconnecting
+    pub warning: u64,
+    pub action: IdleAction,
+    /// Closed regardless of activity
+    pub max_lifetime: Option<u64>,
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            warning: 60,
+            action: IdleAction::Detach,
+            max_lifetime: None,
+        }
+    }
+}
+
+/// Background cleanup of abandoned tmux sessions
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReaperConfig {
+    /// Seconds since the last activity; the reaper is off without it
+    pub max_idle: Option<u64>,
+    /// Seconds between sweeps
+    pub interval: u64,
+    /// Swept in addition to everyone who logged in recently
+    pub users: Vec<String>,
+}
+
+impl Default for ReaperConfig {
+    fn default() -> Self {
+        Self {
+            max_idle: None,
+            interval: 300,
+            users: Vec::new(),
+        }
+    }
+}
+
+/// Prometheus endpoint at `/metrics`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    /// Bearer token scrapers must send; the endpoint is open without it
+    pub token: Option<Redacted>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            token: None,
+        }
+    }
+}
+
+/// SSH reachability checks behind `/health/ready`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Seconds a probe result is reused, so frequent readiness checks do not hammer the hosts
+    pub cache_ttl: u64,
+    /// Seconds a host gets to accept the connection and send its SSH banner
+    pub probe_timeout: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            cache_ttl: 10,
+            probe_timeout: 2,
+        }
+    }
+}
+
+/// Log output, and OpenTelemetry span export over OTLP/HTTP (off without an endpoint)
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub log_format: LogFormat,
+    /// Collector base URL such as `http://localhost:4318`; spans go to `/v1/traces`
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            log_format: LogFormat::Text,
+            otlp_endpoint: None,
+            service_name: "deuseda".to_string(),
+        }
+    }
+}
+
+/// Non-empty value of an environment variable
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn parse_env<T: FromStr>(name: &str) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    env(name)
+        .map(|v| {
+            v.trim()
+                .parse()
+                .map_err(|e| anyhow!("Invalid {}={:?}: {}", name, v, e))
+        })
+        .transpose()
+}
+
+fn set<T: FromStr>(field: &mut T, name: &str) -> Result<()>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Some(value) = parse_env(name)? {
+        *field = value;
+    }
+    Ok(())
+}
+
+fn set_opt<T: FromStr>(field: &mut Option<T>, name: &str) -> Result<()>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Some(value) = parse_env(name)? {
+        *field = Some(value);
+    }
+    Ok(())
+}
+
+/// Comma separated list
+fn set_list(field: &mut Vec<String>, name: &str) {
+    if let Some(value) = env(name) {
+        *field = value
+            .split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+}
+
+fn set_bool(field: &mut bool, name: &str) {
+    if let Some(value) = env(name) {
+        *field = !matches!(value.trim(), "false" | "0");
+    }
+}
+
+impl Config {
+    /// Read the config file, apply environment overrides and validate
+    pub fn load() -> Result<Self> {
+        let (path, required) = match env("DEUSEDA_CONFIG") {
+            Some(path) => (PathBuf::from(path), true),
+            None => (PathBuf::from("deuseda.toml"), false),
+        };
+
+        let mut config = if required || path.exists() {
+            let config = Self::from_file(&path)?;
+            tracing::info!("Loaded configuration from {}", path.display());
+            config
+        } else {
+            Self::default()
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&raw).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Environment variables win over the file
+    fn apply_env(&mut self) -> Result<()> {
+        let server = &mut self.server;
+        set(&mut server.host, "SERVER_HOST")?;
+        set(&mut server.port, "SERVER_PORT")?;
+        set_list(&mut server.listen, "SERVER_LISTEN");
+        set(&mut server.unix_socket_mode, "SERVER_UNIX_SOCKET_MODE")?;
+        set_list(&mut server.cors_allowed_origins, "CORS_ALLOWED_ORIGINS");
+        set_bool(&mut server.trust_proxy_headers, "TRUST_PROXY_HEADERS");
+        set_list(&mut server.trusted_proxies, "TRUSTED_PROXIES");
+        set(&mut server.shutdown_grace, "SERVER_SHUTDOWN_GRACE")?;
+
+        let tls = &mut server.tls;
+        set_opt(&mut tls.cert_file, "TLS_CERT_FILE")?;
+        set_opt(&mut tls.key_file, "TLS_KEY_FILE")?;
+        set_opt(&mut tls.client_ca_file, "TLS_CLIENT_CA_FILE")?;
+        set_opt(&mut tls.http_redirect_port, "TLS_HTTP_REDIRECT_PORT")?;
+
+        let ssh = &mut self.ssh;
+        set(&mut ssh.host, "SSH_HOST")?;
+        set(&mut ssh.port, "SSH_PORT")?;
+        set_list(&mut ssh.hosts, "SSH_HOSTS");
+        set_opt(&mut ssh.user_key_dir, "SSH_USER_KEY_DIR")?;
+
+        let ca = &mut ssh.ca;
+        set_opt(&mut ca.key_file, "SSH_CA_KEY_FILE")?;
+        set(&mut ca.cert_ttl, "SSH_CERT_TTL")?;
+        set_opt(&mut ca.source_address, "SSH_CERT_SOURCE_ADDRESS")?;
+        set_opt(&mut ca.force_command, "SSH_CERT_FORCE_COMMAND")?;
+        set_list(&mut ca.extensions, "SSH_CERT_EXTENSIONS");
+        set_list(&mut ca.extra_principals, "SSH_CERT_EXTRA_PRINCIPALS");
+
+        let auth = &mut self.auth;
+        set_opt(&mut auth.jwt_secret, "JWT_SECRET")?;
+        set_opt(&mut auth.jwt_keyring_file, "JWT_KEYRING_FILE")?;
+        set(&mut auth.access_token_ttl, "ACCESS_TOKEN_TTL")?;
+        set(&mut auth.refresh_token_ttl, "REFRESH_TOKEN_TTL")?;
+        set_opt(&mut auth.rbac_policy_file, "RBAC_POLICY_FILE")?;
+
+        let throttle = &mut auth.throttle;
+        set(
+            &mut throttle.attempts_per_minute,
+            "LOGIN_ATTEMPTS_PER_MINUTE",
+        )?;
+        set(&mut throttle.lockout_after, "LOGIN_LOCKOUT_AFTER")?;
+        set(&mut throttle.lockout_secs, "LOGIN_LOCKOUT_SECS")?;
+        set(&mut throttle.backoff_base_secs, "LOGIN_BACKOFF_BASE_SECS")?;
+
+        let oidc = &mut auth.oidc;
+        set_opt(&mut oidc.issuer, "OIDC_ISSUER")?;
+        set(&mut oidc.client_id, "OIDC_CLIENT_ID")?;
+        set_opt(&mut oidc.client_secret, "OIDC_CLIENT_SECRET")?;
+        set(&mut oidc.redirect_uri, "OIDC_REDIRECT_URI")?;
+        set(&mut oidc.scopes, "OIDC_SCOPES")?;
+        set(&mut oidc.username_claim, "OIDC_USERNAME_CLAIM")?;
+        set_bool(
+            &mut oidc.username_strip_domain,
+            "OIDC_USERNAME_STRIP_DOMAIN",
+        );
+        set(&mut oidc.groups_claim, "OIDC_GROUPS_CLAIM")?;
+        set_list(&mut oidc.allowed_groups, "OIDC_ALLOWED_GROUPS");
+        set(&mut oidc.post_login_url, "OIDC_POST_LOGIN_URL")?;
+        set_list(&mut oidc.id_token_algorithms, "OIDC_ID_TOKEN_ALGORITHMS");
+
+        let terminal = &mut self.terminal;
+        set_bool(
+            &mut terminal.broadcast_enabled,
+            "TERMINAL_BROADCAST_ENABLED",
+        );
+        set(&mut terminal.ticket_ttl, "TERMINAL_TICKET_TTL")?;
+        set(&mut terminal.share_max_ttl, "TERMINAL_SHARE_MAX_TTL")?;
+        set_opt(&mut terminal.max_sessions_per_user, "MAX_SESSIONS_PER_USER")?;
+        set_opt(&mut terminal.max_sessions_per_host, "MAX_SESSIONS_PER_HOST")?;
+        set_opt(
+            &mut terminal.max_tmux_sessions_per_user,
+            "MAX_TMUX_SESSIONS_PER_USER",
+        )?;
+        set(
+            &mut terminal.max_broadcast_groups_per_user,
+            "MAX_BROADCAST_GROUPS_PER_USER",
+        )?;
+
+        let idle = &mut terminal.idle;
+        set_opt(&mut idle.timeout, "TERMINAL_IDLE_TIMEOUT")?;
+        set(&mut idle.warning, "TERMINAL_IDLE_WARNING")?;
+        set(&mut idle.action, "TERMINAL_IDLE_ACTION")?;
+        set_opt(&mut idle.max_lifetime, "TERMINAL_MAX_LIFETIME")?;
+
+        let reaper = &mut self.reaper;
+        set_opt(&mut reaper.max_idle, "TMUX_REAPER_MAX_IDLE")?;
+        set(&mut reaper.interval, "TMUX_REAPER_INTERVAL")?;
+        set_list(&mut reaper.users, "TMUX_REAPER_USERS");
+
+        let metrics = &mut self.metrics;
+        set_bool(&mut metrics.enabled, "METRICS_ENABLED");
+        set_opt(&mut metrics.token, "METRICS_TOKEN")?;
+
+        let health = &mut self.health;
+        set(&mut health.cache_ttl, "HEALTH_CACHE_TTL")?;
+        set(&mut health.probe_timeout, "HEALTH_PROBE_TIMEOUT")?;
+
+        let telemetry = &mut self.telemetry;
+        set(&mut telemetry.log_format, "LOG_FORMAT")?;
+        set_opt(&mut telemetry.otlp_endpoint, "OTEL_EXPORTER_OTLP_ENDPOINT")?;
+        set(&mut telemetry.service_name, "OTEL_SERVICE_NAME")?;
+
+        Ok(())
+    }
+
+    /// Reject settings the server cannot run with, before anything is started
+    fn validate(&self) -> Result<()> {
+        self.server.listen_addrs()?;
+        self.server.unix_socket_mode()?;
+        self.server.trusted_proxies()?;
+        let tls = &self.server.tls;
+        if tls.cert_file.is_some() != tls.key_file.is_some() {
+            bail!("server.tls.cert_file and key_file (TLS_CERT_FILE, TLS_KEY_FILE) go together");
+        }
+        if !tls.enabled() && (tls.client_ca_file.is_some() || tls.http_redirect_port.is_some()) {
+            bail!("server.tls.client_ca_file and http_redirect_port need TLS enabled");
+        }
+
+        if self.ssh.host.trim().is_empty() {
+            bail!("ssh.host (SSH_HOST) must not be empty");
+        }
+        if let Some(host) = self
+            .ssh
+            .hosts
+            .iter()
+            .find(|h| SshTarget::parse(h).is_none())
+        {
+            bail!("ssh.hosts (SSH_HOSTS): invalid host {:?}", host);
+        }
+
+        let auth = &self.auth;
+        if auth.jwt_keyring_file.is_none() && auth.jwt_secret.is_none() {
+            bail!("auth.jwt_secret (JWT_SECRET) must be set unless auth.jwt_keyring_file is");
+        }
+        if auth.access_token_ttl <= 0 || auth.refresh_token_ttl <= 0 {
+            bail!("auth.access_token_ttl and auth.refresh_token_ttl must be positive");
+        }
+        if auth.throttle.lockout_after == 0 || auth.throttle.attempts_per_minute == 0 {
+            bail!("auth.throttle.lockout_after and attempts_per_minute must be positive");
+        }
+        if let Some(issuer) = &auth.oidc.issuer {
+            url::Url::parse(issuer).with_context(|| format!("Invalid OIDC issuer {}", issuer))?;
+            url::Url::parse(&auth.oidc.redirect_uri)
+                .with_context(|| format!("Invalid OIDC redirect URI {}", auth.oidc.redirect_uri))?;
+            crate::auth::oidc::id_token_algorithms(&auth.oidc)?;
+        }
+
+        let terminal = &self.terminal;
+        if terminal.ticket_ttl == 0 || terminal.share_max_ttl == 0 {
+            bail!("terminal.ticket_ttl and terminal.share_max_ttl must be positive");
+        }
+        let limits = [
+            terminal.max_sessions_per_user,
+            terminal.max_sessions_per_host,
+            terminal.max_tmux_sessions_per_user,
+        ];
+        if limits.contains(&Some(0)) {
+            bail!("Terminal limits must be positive; leave them unset for no limit");
+        }
+        if terminal.max_broadcast_groups_per_user == 0 {
+            bail!("terminal.max_broadcast_groups_per_user must be positive");
+        }
+        if terminal.idle.timeout == Some(0) || terminal.idle.max_lifetime == Some(0) {
+            bail!("terminal.idle.timeout and max_lifetime must be positive when set");
+        }
+
+        if self.reaper.max_idle == Some(0) || self.reaper.interval == 0 {
+            bail!("reaper.max_idle and reaper.interval must be positive");
+        }
+
+        if self.health.probe_timeout == 0 {
+            bail!("health.probe_timeout must be positive");
+        }
+
+        if let Some(endpoint) = &self.telemetry.otlp_endpoint {
+            url::Url::parse(endpoint)
+                .with_context(|| format!("Invalid telemetry.otlp_endpoint {}", endpoint))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Current configuration, swapped on `SIGHUP`
+pub struct LiveConfig {
+    current: RwLock<Arc<Config>>,
+    /// Ticks on every successful reload, for tasks that wait on a setting
+    reloaded: watch::Sender<()>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Config) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(config)),
+            reloaded: watch::Sender::new(()),
+        }
+    }
+
+    /// Changes after every reload
+    pub fn subscribe(&self) -> watch::Receiver<()> {
+        self.reloaded.subscribe()
+    }
+
+    /// Snapshot of the current settings; a later reload does not change it
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Reload file and environment. Only `[ssh]` (except `[ssh.ca]`), `[terminal]`, `[reaper]`,
+    /// `[metrics]`, `[health]`, `server.trust_proxy_headers` and `server.trusted_proxies` take
+    /// effect; the listener, CORS, keys, SSO, the CA, the RBAC policy and `[telemetry]` need a
+    /// restart.
+    pub fn reload(&self) -> Result<()> {
+        let mut next = Config::load()?;
+        let current = self.get();
+
+        let mut structural = current.server.clone();
+        structural.trust_proxy_headers = next.server.trust_proxy_headers;
+        structural.trusted_proxies = next.server.trusted_proxies.clone();
+        if next.server != structural
+            || next.auth != current.auth
+            || next.ssh.ca != current.ssh.ca
+            || next.telemetry != current.telemetry
+        {
+            tracing::warn!(
+                "Changes to [server], [auth], [ssh.ca] and [telemetry] are ignored until the next restart"
+            );
+        }
+        next.server = structural;
+        next.auth = current.auth.clone();
+        next.ssh.ca = current.ssh.ca.clone();
+        next.telemetry = current.telemetry.clone();
+
+        *self.current.write().unwrap() = Arc::new(next);
+        self.reloaded.send_replace(());
+        tracing::info!("Configuration reloaded");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_output_hides_secrets() {
+        let mut config = Config::default();
+        config.auth.jwt_secret = Some("jwt-secret-value".parse().unwrap());
+        config.auth.oidc.client_secret = Some("oidc-secret-value".parse().unwrap());
+        config.metrics.token = Some("metrics-token-value".parse().unwrap());
+
+        let debug = format!("{:?}", config);
+        for secret in [
+            "jwt-secret-value",
+            "oidc-secret-value",
+            "metrics-token-value",
+        ] {
+            assert!(!debug.contains(secret), "{secret} leaked");
+        }
+        assert!(debug.contains("[REDACTED]"));
+    }
+}
@@ -0,0 +1,141 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use uuid::Uuid;
+
+use crate::{
+    auth::rbac::RbacPolicy,
+    handlers::broadcast::to_response,
+    middleware::auth::{Admin, Claims},
+    models::{BroadcastGroupResponse, ConnectionResponse, TerminateQuery, TerminatedResponse},
+    state::AppState,
+    terminal::registry::BridgeHandle,
+};
+
+/// Shown to the user when no reason is given
+const DEFAULT_TERMINATE_MESSAGE: &str = "Your terminal was closed by an administrator";
+
+// GET /api/admin/rbac - Effective role policy (admins only)
+pub async fn rbac_policy(State(state): State<AppState>, Admin(claims): Admin) -> Json<RbacPolicy> {
+    tracing::info!("Admin {} read the RBAC policy", claims.username());
+
+    Json(state.rbac.as_ref().clone())
+}
+
+// GET /api/admin/connections - Every open terminal, oldest first
+pub async fn list_connections(
+    State(state): State<AppState>,
+    Admin(_): Admin,
+) -> Json<Vec<ConnectionResponse>> {
+    let mut bridges = state.bridges.all();
+    bridges.sort_by_key(|b| b.started_at);
+
+    Json(bridges.iter().map(connection_response).collect())
+}
+
+// DELETE /api/admin/connections/:id - Close one terminal; `?reason=` is shown to the user
+pub async fn terminate_connection(
+    State(state): State<AppState>,
+    Admin(claims): Admin,
+    Path(id): Path<Uuid>,
+    Query(query): Query<TerminateQuery>,
+) -> Result<StatusCode, StatusCode> {
+    let bridge = state.bridges.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    terminate(&claims, &bridge, query.reason.as_deref());
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// DELETE /api/admin/users/:username/connections - Close every terminal of a user
+pub async fn terminate_user_connections(
+    State(state): State<AppState>,
+    Admin(claims): Admin,
+    Path(username): Path<String>,
+    Query(query): Query<TerminateQuery>,
+) -> Json<TerminatedResponse> {
+    let bridges = state.bridges.of_user(&username);
+    for bridge in &bridges {
+        terminate(&claims, bridge, query.reason.as_deref());
+    }
+
+    Json(TerminatedResponse {
+        terminated: bridges.len(),
+    })
+}
+
+// GET /api/admin/broadcast-groups - Broadcast groups of every user
+pub async fn list_broadcast_groups(
+    State(state): State<AppState>,
+    Admin(_): Admin,
+) -> Json<Vec<BroadcastGroupResponse>> {
+    let groups = state.broadcasts.all();
+
+    Json(groups.iter().map(|g| to_response(&state, g)).collect())
+}
+
+// DELETE /api/admin/broadcast-groups/:group_id - Stop any user's broadcast group
+pub async fn stop_broadcast_group(
+    State(state): State<AppState>,
+    Admin(claims): Admin,
+    Path(group_id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    let group = state
+        .broadcasts
+        .stop(&group_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    tracing::info!(
+        target: "deuseda::audit",
+        event = "broadcast_stopped",
+        admin = %claims.username(),
+        username = %group.owner,
+        group = %group.id,
+        "Admin {} stopped broadcast group {} of {}",
+        claims.username(),
+        group.id,
+        group.owner
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn terminate(admin: &Claims, bridge: &BridgeHandle, reason: Option<&str>) {
+    let message = reason
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .unwrap_or(DEFAULT_TERMINATE_MESSAGE);
+
+    tracing::info!(
+        target: "deuseda::audit",
+        event = "terminal_terminated",
+        admin = %admin.username(),
+        username = %bridge.username,
+        host = %bridge.target,
+        session = %bridge.session_id,
+        client_ip = %bridge.client.ip,
+        reason = %message,
+        "Admin {} closed the terminal of {} on {} ({})",
+        admin.username(),
+        bridge.username,
+        bridge.target,
+        bridge.session_id
+    );
+    bridge.terminate(message);
+}
+
+fn connection_response(bridge: &BridgeHandle) -> ConnectionResponse {
+    ConnectionResponse {
+        id: bridge.id,
+        username: bridge.username.clone(),
+        host: bridge.target.to_string(),
+        session_id: bridge.session_id.clone(),
+        client_ip: bridge.client.ip.to_string(),
+        user_agent: bridge.client.user_agent.clone(),
+        started_at: bridge.started_at,
+        bytes_in: bridge.stats.bytes_in(),
+        bytes_out: bridge.stats.bytes_out(),
+        last_activity: bridge.stats.last_activity(),
+    }
+}
@@ -1,11 +1,176 @@
-use crate::{auth as auth_service, models::*};
-use axum::{http::StatusCode, Json};
+use std::net::SocketAddr;
 
+use crate::{
+    auth as auth_service,
+    metrics::metrics,
+    middleware::{auth::Claims, client_ip},
+    models::*,
+    state::AppState,
+    terminal::exit::ExitReason,
+};
+use axum::{
+    extract::{ConnectInfo, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Extension, Json,
+};
+use jsonwebtoken::jwk::JwkSet;
+
+// POST /api/auth/login - Verify SSH credentials; throttled per client IP and username
 pub async fn login(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Json(payload): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, Response> {
+    let config = state.config.get();
+    let ip = client_ip(&headers, peer, &config.server);
+    let username = payload.username.clone();
+
+    let attempt = match state.login_throttle.reserve(ip, &username) {
+        Ok(attempt) => attempt,
+        Err(retry_after) => {
+            tracing::warn!("Login for {} from {} throttled", username, ip);
+            metrics().login("password", "throttled");
+            // Round up so clients never retry a moment too early
+            let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, secs.to_string())],
+                "Too many login attempts, try again later".to_string(),
+            )
+                .into_response());
+        }
+    };
+
+    match auth_service::authenticate_user(&state.tokens, &state.rbac, &config.ssh, payload).await {
+        Ok(response) => {
+            attempt.succeeded();
+            metrics().login("password", "success");
+            Ok(Json(response))
+        }
+        // Only rejected credentials count towards the lockout
+        Err(e) if matches!(e.downcast_ref(), Some(ExitReason::AuthFailed { .. })) => {
+            attempt.failed();
+            metrics().login("password", "failure");
+            Err((
+                StatusCode::UNAUTHORIZED,
+                "Invalid username or password".to_string(),
+            )
+                .into_response())
+        }
+        Err(e) => {
+            // Details stay in the log; they would tell an attacker about the SSH server
+            tracing::error!("Login for {} could not be verified: {:#}", username, e);
+            metrics().login("password", "error");
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Login is unavailable right now, try again later".to_string(),
+            )
+                .into_response())
+        }
+    }
+}
+
+// GET /api/auth/providers - Login methods the frontend should offer
+pub async fn providers(State(state): State<AppState>) -> Json<AuthProvidersResponse> {
+    Json(AuthProvidersResponse {
+        password: true,
+        oidc: state.oidc.is_some(),
+        ssh_certificates: state.ssh_ca.is_some(),
+    })
+}
+
+// GET /api/auth/oidc/login - Send the browser to the identity provider
+pub async fn oidc_login(State(state): State<AppState>) -> Result<Redirect, (StatusCode, String)> {
+    let oidc = state.oidc.ok_or((
+        StatusCode::NOT_FOUND,
+        "SSO login is not configured".to_string(),
+    ))?;
+
+    oidc.authorization_url()
+        .await
+        .map(|url| Redirect::to(&url))
+        .map_err(|e| {
+            tracing::error!("Failed to start SSO login: {:#}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                "Identity provider is unavailable".to_string(),
+            )
+        })
+}
+
+// GET /api/auth/oidc/callback - Identity provider redirect; hands the tokens to the frontend
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    Query(params): Query<OidcCallbackQuery>,
+) -> Result<Redirect, StatusCode> {
+    let oidc = state.oidc.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let config = state.config.get();
+
+    let result = match (params.code, params.state, params.error) {
+        (Some(code), Some(login_state), None) => {
+            auth_service::sso_login(
+                &state.tokens,
+                oidc,
+                state.ssh_ca.as_deref(),
+                &state.rbac,
+                &config.ssh,
+                &code,
+                &login_state,
+            )
+            .await
+        }
+        (_, _, error) => Err(anyhow::anyhow!(
+            "{}",
+            params
+                .error_description
+                .or(error)
+                .unwrap_or_else(|| "Invalid SSO callback".to_string())
+        )),
+    };
+
+    metrics().login("sso", if result.is_ok() { "success" } else { "failure" });
+    let url = match result {
+        Ok(login) => oidc.post_login_redirect(&[
+            ("token", &login.token),
+            ("refresh_token", &login.refresh_token),
+            ("expires_in", &login.expires_in.to_string()),
+            ("username", &login.username),
+            ("role", login.role.as_str()),
+        ]),
+        Err(e) => {
+            tracing::warn!("SSO login failed: {:#}", e);
+            oidc.post_login_redirect(&[("sso_error", &e.to_string())])
+        }
+    };
+
+    Ok(Redirect::to(&url))
+}
+
+// POST /api/auth/refresh - Rotate the refresh token and issue a new access token
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshRequest>,
 ) -> Result<Json<LoginResponse>, (StatusCode, String)> {
-    auth_service::authenticate_user(payload)
+    auth_service::refresh_session(&state.tokens, payload)
         .await
         .map(Json)
         .map_err(|e| (StatusCode::UNAUTHORIZED, e.to_string()))
 }
+
+// POST /api/auth/logout - Revoke the token and its refresh family, closing open terminals
+pub async fn logout(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> StatusCode {
+    state.tokens.revoke(&claims);
+    tracing::info!("User {} logged out", claims.username());
+
+    StatusCode::NO_CONTENT
+}
+
+/// Public keys of the asymmetric signing keys, so other services can verify access tokens
+pub async fn jwks(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.tokens.keys().jwks())
+}
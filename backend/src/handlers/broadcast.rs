@@ -0,0 +1,257 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::mpsc::error::SendTimeoutError;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::{
+    auth::{rbac::Role, tickets::TicketScope},
+    middleware::auth::{websocket_claims, Operator},
+    models::{BroadcastGroupResponse, BroadcastTargetResponse, CreateBroadcastGroupRequest},
+    state::AppState,
+    terminal::{
+        broadcast::{BroadcastGroup, BroadcastTarget},
+        exit::ExitReason,
+        is_valid_session_name,
+    },
+};
+
+/// How long a target may keep its input queue full before the group is stopped
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) fn to_response(state: &AppState, group: &BroadcastGroup) -> BroadcastGroupResponse {
+    let bridges = group.resolve_bridges(&state.bridges);
+
+    BroadcastGroupResponse {
+        id: group.id.to_string(),
+        owner: group.owner.clone(),
+        targets: group
+            .targets
+            .iter()
+            .zip(bridges)
+            .map(|(t, bridge)| BroadcastTargetResponse {
+                host: t.target.to_string(),
+                session_id: t.session_id.clone(),
+                connected: bridge.is_some(),
+            })
+            .collect(),
+    }
+}
+
+// POST /api/broadcast-groups - Create a group of tmux sessions that share keyboard input
+pub async fn create_group(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+    Json(req): Json<CreateBroadcastGroupRequest>,
+) -> Result<Json<BroadcastGroupResponse>, StatusCode> {
+    let config = state.config.get();
+    if !config.terminal.broadcast_enabled {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if req.targets.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut targets = Vec::with_capacity(req.targets.len());
+    for t in req.targets {
+        if !is_valid_session_name(&t.session_id) {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        let target = claims
+            .resolve_target(&config.ssh, t.host.as_deref())
+            .ok_or(StatusCode::FORBIDDEN)?;
+        let target = BroadcastTarget {
+            target,
+            session_id: t.session_id,
+        };
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    let limit = config.terminal.max_broadcast_groups_per_user;
+    let group = state
+        .broadcasts
+        .create(claims.username(), targets, limit)
+        .ok_or_else(|| {
+            tracing::warn!(
+                "User {} already has {} broadcast groups",
+                claims.username(),
+                limit
+            );
+            StatusCode::TOO_MANY_REQUESTS
+        })?;
+    tracing::info!(
+        "Created broadcast group {} for user {} ({} targets)",
+        group.id,
+        group.owner,
+        group.targets.len()
+    );
+
+    Ok(Json(to_response(&state, &group)))
+}
+
+// GET /api/broadcast-groups - List the current user's broadcast groups
+pub async fn list_groups(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+) -> Json<Vec<BroadcastGroupResponse>> {
+    let groups = state.broadcasts.list_for(claims.username());
+
+    Json(groups.iter().map(|g| to_response(&state, g)).collect())
+}
+
+// DELETE /api/broadcast-groups/:group_id - Kill switch: stop broadcasting immediately
+pub async fn delete_group(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+    Path(group_id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    let group = state
+        .broadcasts
+        .get(&group_id)
+        .filter(|g| g.owner == claims.username())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    state.broadcasts.stop(&group.id);
+    tracing::info!("Stopped broadcast group {}", group.id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct BroadcastWsQuery {
+    /// One-time ticket from `POST /api/terminal-tickets`
+    ticket: Option<String>,
+    group_id: Uuid,
+}
+
+// GET /ws/broadcast - Every input frame is written to all sessions of the group
+pub async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<BroadcastWsQuery>,
+) -> Result<Response, StatusCode> {
+    state.origins.check_websocket(&headers)?;
+
+    let scope = TicketScope::Broadcast {
+        group_id: params.group_id,
+    };
+    let (claims, ws) = websocket_claims(&state, &headers, ws, params.ticket.as_deref(), scope)?;
+    claims.require(Role::Operator)?;
+
+    state
+        .broadcasts
+        .get(&params.group_id)
+        .filter(|g| g.owner == claims.username())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let group = state
+        .broadcasts
+        .attach(&params.group_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let family = claims.family();
+    let span = tracing::info_span!("broadcast", user = %group.owner, group_id = %group.id);
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, group, family).instrument(span)))
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    group: BroadcastGroup,
+    family: Option<Uuid>,
+) {
+    tracing::info!(
+        "Broadcast connection established for user: {} (group: {})",
+        group.owner,
+        group.id
+    );
+
+    let (mut sender, mut receiver) = socket.split();
+    let mut stop = group.subscribe_stop();
+    let mut revocations = state.tokens.watch_revocation(family);
+
+    let started = json!({ "type": "broadcast_started", "group": to_response(&state, &group) });
+    let _ = sender.send(Message::Text(started.to_string())).await;
+
+    loop {
+        tokio::select! {
+            _ = stop.changed() => {
+                let stopped = json!({ "type": "broadcast_stopped" });
+                let _ = sender.send(Message::Text(stopped.to_string())).await;
+                let _ = sender.send(Message::Close(None)).await;
+                break;
+            }
+            _ = revocations.revoked() => {
+                let reason = ExitReason::SessionRevoked;
+                let _ = sender.send(reason.frame()).await;
+                let _ = sender.send(reason.close_frame()).await;
+                break;
+            }
+            msg = receiver.next() => {
+                let data = match msg {
+                    Some(Ok(Message::Text(text))) => text.into_bytes(),
+                    Some(Ok(Message::Binary(data))) => data,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Err(e)) => {
+                        tracing::error!("Broadcast WebSocket error: {}", e);
+                        break;
+                    }
+                    _ => continue,
+                };
+                if let Err(error) = fan_out(&state, &group, data).await {
+                    tracing::warn!("Stopping broadcast group {}: {}", group.id, error);
+                    let stopped = json!({ "type": "broadcast_stopped", "error": error });
+                    let _ = sender.send(Message::Text(stopped.to_string())).await;
+                    let _ = sender.send(Message::Close(None)).await;
+                    break;
+                }
+            }
+        }
+    }
+
+    // Nobody is left to type into the group or to stop it
+    if state.broadcasts.stop(&group.id).is_some() {
+        tracing::info!("Removed broadcast group {} of {}", group.id, group.owner);
+    }
+    tracing::info!("Broadcast connection closed (group: {})", group.id);
+}
+
+/// Write input to every live target. A full input queue holds the socket back (the next
+/// frame is not read) instead of dropping keystrokes: a host that misses part of a command
+/// runs a different one. A target still full after `STALL_TIMEOUT` fails the group.
+async fn fan_out(state: &AppState, group: &BroadcastGroup, data: Vec<u8>) -> Result<(), String> {
+    // Broadcasting may have been turned off by a reload since the group was created
+    if !state.config.get().terminal.broadcast_enabled {
+        tracing::info!("Broadcasting is disabled, stopping group {}", group.id);
+        state.broadcasts.stop(&group.id);
+        return Ok(());
+    }
+    for bridge in group.input_bridges(&state.bridges) {
+        match bridge.input.send_timeout(data.clone(), STALL_TIMEOUT).await {
+            Ok(()) | Err(SendTimeoutError::Closed(_)) => {}
+            Err(SendTimeoutError::Timeout(_)) => {
+                return Err(format!(
+                    "Session {} on {} stopped accepting input, the last input may have \
+                     reached only some sessions",
+                    bridge.session_id, bridge.target
+                ));
+            }
+        }
+    }
+    Ok(())
+}
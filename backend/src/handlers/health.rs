@@ -0,0 +1,70 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use serde_json::{json, Value};
+
+use super::metrics::bearer_matches;
+use crate::{
+    auth::rbac::Role,
+    middleware::auth::verify_token,
+    models::{DependencyCheck, ReadinessResponse},
+    state::AppState,
+};
+
+// GET /health/live (and /health) - The process is up and serving; dependencies are not checked
+pub async fn live() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+// GET /health/ready - Whether this instance can log users in. Passwords are verified on the
+// default SSH host, so it failing makes the instance unready; other hosts only degrade it.
+// Per-host results (addresses, banners, errors) need an admin token or the metrics token.
+pub async fn ready(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> (StatusCode, Json<ReadinessResponse>) {
+    let config = state.config.get();
+    let default = config.ssh.default_target();
+    let targets = config.ssh.allowed_targets();
+    let results = state.ssh_probe.check(&targets, &config.health).await;
+
+    let checks: Vec<DependencyCheck> = targets
+        .into_iter()
+        .zip(results)
+        .map(|(target, result)| DependencyCheck {
+            name: "ssh",
+            required: target == default,
+            target: target.to_string(),
+            result,
+        })
+        .collect();
+
+    let (code, status) = if checks.iter().any(|c| c.required && !c.result.up) {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    } else if checks.iter().any(|c| !c.result.up) {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+
+    let checks = may_see_details(&state, &headers).then_some(checks);
+    (code, Json(ReadinessResponse { status, checks }))
+}
+
+/// Admin access token, or the metrics token when one is configured
+fn may_see_details(state: &AppState, headers: &HeaderMap) -> bool {
+    if let Some(token) = &state.config.get().metrics.token {
+        if bearer_matches(headers, token) {
+            return true;
+        }
+    }
+
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(|token| verify_token(&state.tokens, token).ok())
+        .is_some_and(|claims| claims.role >= Role::Admin)
+}
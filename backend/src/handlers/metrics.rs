@@ -0,0 +1,41 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+
+use crate::{config::Redacted, metrics::metrics, state::AppState};
+
+/// Whether the request carries `Authorization: Bearer <token>`
+pub(crate) fn bearer_matches(headers: &HeaderMap, token: &Redacted) -> bool {
+    let sent = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    // Compare digests so the time taken does not depend on how much of the token matched
+    sent.map(Sha256::digest) == Some(Sha256::digest(token.expose()))
+}
+
+// GET /metrics - Prometheus scrape endpoint; requires `Authorization: Bearer` when [metrics] token is set
+pub async fn scrape(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let config = state.config.get();
+    if !config.metrics.enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    if let Some(token) = &config.metrics.token {
+        if !bearer_matches(&headers, token) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    let metrics = metrics();
+    metrics.terminals.set(state.bridges.count() as i64);
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.encode(),
+    )
+        .into_response()
+}
@@ -1,10 +1,10 @@
+pub mod admin;
 pub mod auth;
+pub mod broadcast;
+pub mod health;
+pub mod metrics;
+pub mod share;
 pub mod terminal;
+pub mod terminal_mux;
 pub mod terminal_session;
-
-use axum::Json;
-use serde_json::{json, Value};
-
-pub async fn health_check() -> Json<Value> {
-    Json(json!({ "status": "ok" }))
-}
+pub mod ticket;
@@ -0,0 +1,285 @@
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    Json,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use super::terminal::{apply_control, TerminalMessage};
+use crate::{
+    auth::rbac::Role,
+    middleware::auth::Operator,
+    models::{CreateShareRequest, ShareResponse},
+    state::AppState,
+    terminal::{
+        exit::ExitReason,
+        registry::{BridgeEvent, BridgeHandle},
+        share::{ShareLink, ShareScope},
+    },
+};
+
+/// Default lifetime of a share link
+const DEFAULT_SHARE_TTL_SECS: u64 = 60 * 60;
+
+impl From<ShareLink> for ShareResponse {
+    fn from(link: ShareLink) -> Self {
+        Self {
+            token: link.token,
+            session_id: link.session_id,
+            scope: link.scope,
+            expires_at: link.expires_at,
+        }
+    }
+}
+
+// POST /api/terminal-shares - Mint a share link for one of the caller's live terminals
+pub async fn create_share(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+    Json(req): Json<CreateShareRequest>,
+) -> Result<Json<ShareResponse>, StatusCode> {
+    let config = state.config.get();
+    let username = claims.username();
+    let target = claims
+        .resolve_target(&config.ssh, req.host.as_deref())
+        .ok_or(StatusCode::FORBIDDEN)?;
+
+    // Only an open /ws/terminal connection can be shared
+    let bridge = state
+        .bridges
+        .find_session(username, &target, &req.session_id)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let ttl = req
+        .expires_in
+        .unwrap_or(DEFAULT_SHARE_TTL_SECS)
+        .clamp(1, config.terminal.share_max_ttl);
+    let scope = req.scope.unwrap_or(ShareScope::ReadOnly);
+
+    let link = state.shares.create(
+        bridge.id,
+        username,
+        &req.session_id,
+        scope,
+        chrono::Duration::seconds(ttl as i64),
+    );
+
+    tracing::info!(
+        "User {} shared session {} ({:?}, expires {})",
+        username,
+        req.session_id,
+        scope,
+        link.expires_at
+    );
+
+    Ok(Json(link.into()))
+}
+
+// GET /api/terminal-shares - List the caller's active share links
+pub async fn list_shares(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+) -> Json<Vec<ShareResponse>> {
+    let links = state.shares.list_for(claims.username());
+
+    Json(links.into_iter().map(Into::into).collect())
+}
+
+// DELETE /api/terminal-shares/:token - Revoke a share link and disconnect its viewers
+pub async fn revoke_share(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+    Path(token): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    state
+        .shares
+        .get(&token)
+        .filter(|l| l.owner == claims.username())
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    state.shares.revoke(&token);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct ShareWsQuery {
+    /// One-time ticket from `POST /api/terminal-tickets`, which names the share link
+    ticket: String,
+}
+
+// GET /ws/terminal/share - Watch a shared terminal; the viewer logs in with their own account
+pub async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<ShareWsQuery>,
+) -> Result<Response, StatusCode> {
+    state.origins.check_websocket(&headers)?;
+
+    let (claims, share) = state
+        .tickets
+        .redeem_share(&params.ticket)
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    // The session may have been logged out since the ticket was issued
+    if state.tokens.is_revoked(&claims) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let family = claims.family();
+
+    let mut link = state.shares.get(&share).ok_or(StatusCode::NOT_FOUND)?;
+    // Viewers always watch read-only, whatever the link allows
+    if claims.role < Role::Operator {
+        link.scope = ShareScope::ReadOnly;
+    }
+    let viewer = claims.sub;
+    let bridge = state.bridges.get(&link.bridge_id).ok_or(StatusCode::GONE)?;
+
+    let span = tracing::info_span!(
+        "terminal_share",
+        user = %viewer,
+        owner = %link.owner,
+        session_id = %link.session_id,
+    );
+    Ok(ws.on_upgrade(move |socket| {
+        handle_socket(socket, state, link, bridge, viewer, family).instrument(span)
+    }))
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    link: ShareLink,
+    bridge: BridgeHandle,
+    viewer: String,
+    family: Option<Uuid>,
+) {
+    tracing::info!(
+        "User {} joined shared session {} of {} ({:?})",
+        viewer,
+        link.session_id,
+        link.owner,
+        link.scope
+    );
+
+    let (mut sender, mut receiver) = socket.split();
+    let mut output = bridge.output.subscribe();
+    let mut events = bridge.events.subscribe();
+    let mut revocations = state.tokens.watch_revocation(family);
+
+    // Every viewer shows up in presence; read-write viewers may take the keyboard
+    let participant = Uuid::new_v4();
+    let _ = bridge.events.send(BridgeEvent::ViewerJoined {
+        viewer: viewer.clone(),
+        scope: link.scope,
+    });
+    bridge
+        .collab
+        .join(participant, &viewer, link.scope == ShareScope::ReadWrite);
+
+    // Expiry and revocation are checked once per second
+    let mut link_check = tokio::time::interval(std::time::Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            data = output.recv() => match data {
+                Ok(data) => {
+                    if sender.send(Message::Binary(data)).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Share viewer {} lagged, skipped {} chunks", viewer, skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            Ok(event) = events.recv() => {
+                let frame = serde_json::to_string(&event).unwrap_or_default();
+                if sender.send(Message::Text(frame)).await.is_err() {
+                    break;
+                }
+            }
+            _ = bridge.input.closed() => {
+                let ended = json!({ "type": "share_ended", "reason": "terminal_closed" });
+                let _ = sender.send(Message::Text(ended.to_string())).await;
+                break;
+            }
+            _ = revocations.revoked() => {
+                let reason = ExitReason::SessionRevoked;
+                let _ = sender.send(reason.frame()).await;
+                break;
+            }
+            _ = link_check.tick() => {
+                if state.shares.get(&link.token).is_none() {
+                    let ended = json!({ "type": "share_ended", "reason": "link_expired" });
+                    let _ = sender.send(Message::Text(ended.to_string())).await;
+                    break;
+                }
+            }
+            msg = receiver.next() => match msg {
+                Some(Ok(msg @ (Message::Text(_) | Message::Binary(_)))) => {
+                    let bridge_alive = forward_input(&link, &bridge, participant, msg).await;
+                    if !bridge_alive {
+                        break;
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Err(e)) => {
+                    tracing::error!("Share viewer WebSocket error: {}", e);
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let _ = sender.send(Message::Close(None)).await;
+    bridge.collab.leave(participant);
+    let _ = bridge.events.send(BridgeEvent::ViewerLeft {
+        viewer: viewer.clone(),
+    });
+
+    tracing::info!("User {} left shared session {}", viewer, link.session_id);
+}
+
+/// Forward viewer keystrokes and keyboard control messages; returns false once the bridge is gone
+async fn forward_input(
+    link: &ShareLink,
+    bridge: &BridgeHandle,
+    participant: Uuid,
+    msg: Message,
+) -> bool {
+    if link.scope != ShareScope::ReadWrite {
+        return true;
+    }
+
+    let data = match msg {
+        Message::Text(text) => match serde_json::from_str::<TerminalMessage>(&text) {
+            // Viewers cannot resize the owner's PTY
+            Ok(TerminalMessage::Resize { .. }) => return true,
+            Ok(control) => {
+                apply_control(bridge, participant, control);
+                return true;
+            }
+            Err(_) => text.into_bytes(),
+        },
+        Message::Binary(data) => data,
+        _ => return true,
+    };
+
+    // Only the keyboard holder's input reaches the terminal
+    if !bridge.collab.holds_keyboard(participant) {
+        return true;
+    }
+
+    bridge.input.send(data).await.is_ok()
+}
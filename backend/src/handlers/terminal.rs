@@ -1,77 +1,208 @@
-use crate::middleware::auth::Claims;
+use crate::{
+    auth::{rbac::Role, tickets::TicketScope},
+    middleware::auth::{websocket_claims, Claims},
+    ssh::{SshAuth, SshTarget},
+    state::AppState,
+    telemetry::scrub::Secret,
+    terminal::{
+        connection::{ChannelCommand, ChannelEvent, SshConnection},
+        exit::ExitReason,
+        idle::IdlePolicy,
+        is_valid_session_name,
+        registry::{BridgeHandle, ClientInfo},
+    },
+};
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
-        Query,
+        ConnectInfo, Query, State,
     },
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::Response,
 };
-use futures_util::{SinkExt, StreamExt};
-use jsonwebtoken::{decode, DecodingKey, Validation};
+use futures_util::{stream::SplitStream, SinkExt, StreamExt};
 use serde::Deserialize;
 use serde_json;
-use ssh2::Session;
-use std::net::TcpStream;
-use tokio::sync::mpsc;
+use std::net::SocketAddr;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{Instrument, Span};
+use uuid::Uuid;
 
 #[derive(Deserialize)]
 pub struct WsQuery {
-    token: Option<String>,
+    /// One-time ticket from `POST /api/terminal-tickets`
+    ticket: Option<String>,
     session_id: Option<String>,
+    host: Option<String>,
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
-enum TerminalMessage {
+pub(crate) enum TerminalMessage {
     #[serde(rename = "resize")]
     Resize { cols: u32, rows: u32 },
+    #[serde(rename = "request_control")]
+    RequestControl,
+    #[serde(rename = "grant_control")]
+    GrantControl { to: String },
+    #[serde(rename = "revoke_control")]
+    RevokeControl,
+}
+
+/// Apply a keyboard control message sent by `participant` (resize is handled by the caller)
+pub(crate) fn apply_control(bridge: &BridgeHandle, participant: Uuid, msg: TerminalMessage) {
+    let result = match msg {
+        TerminalMessage::RequestControl => {
+            bridge.collab.request(participant);
+            Ok(())
+        }
+        TerminalMessage::GrantControl { to } => bridge.collab.grant(participant, &to),
+        TerminalMessage::RevokeControl => bridge.collab.revoke(participant),
+        TerminalMessage::Resize { .. } => Ok(()),
+    };
+
+    if let Err(e) = result {
+        tracing::warn!(
+            "Rejected keyboard control on session {}: {}",
+            bridge.session_id,
+            e
+        );
+    }
+}
+
+/// SSH credentials for a terminal socket: certificates from the embedded CA or the
+/// SSO user's key when the server holds them, otherwise the password sent as the first frame.
+/// `ClientClosed` means the socket went away before sending one.
+pub(crate) async fn ssh_credentials(
+    state: &AppState,
+    sso: bool,
+    username: &str,
+    receiver: &mut SplitStream<WebSocket>,
+) -> Result<SshAuth, ExitReason> {
+    let ssh = &state.config.get().ssh;
+    match SshAuth::from_server(ssh, state.ssh_ca.as_deref(), username, sso) {
+        Ok(Some(auth)) => return Ok(auth),
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!("No SSH credentials for {}: {:#}", username, e);
+            return Err(ExitReason::AuthFailed {
+                message: e.to_string(),
+            });
+        }
+    }
+
+    // Wait for password from first message (sent by frontend)
+    match receiver.next().await {
+        Some(Ok(Message::Text(pwd))) => Ok(SshAuth::Password(Secret::new(pwd.trim()))),
+        Some(Ok(Message::Binary(data))) => Ok(SshAuth::Password(Secret::new(
+            String::from_utf8_lossy(&data).trim(),
+        ))),
+        None | Some(Ok(Message::Close(_))) | Some(Err(_)) => Err(ExitReason::ClientClosed),
+        Some(Ok(_)) => Err(ExitReason::AuthFailed {
+            message: "No password received".to_string(),
+        }),
+    }
 }
 
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     Query(params): Query<WsQuery>,
 ) -> Result<Response, StatusCode> {
-    // Validate JWT token from query params
-    let token = params.token.ok_or(StatusCode::UNAUTHORIZED)?;
+    // Only pages served from an allowed origin may open terminals
+    state.origins.check_websocket(&headers)?;
 
-    let secret = std::env::var("JWT_SECRET")
-        .expect("JWT_SECRET environment variable must be set for production use");
+    let session_id = params.session_id.unwrap_or_else(|| "default".to_string());
+    if !is_valid_session_name(&session_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
 
-    let token_data = decode::<Claims>(
-        &token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let scope = TicketScope::Terminal {
+        session_id: session_id.clone(),
+        host: params.host.clone(),
+    };
+    let (claims, ws) = websocket_claims(&state, &headers, ws, params.ticket.as_deref(), scope)?;
 
-    // Get username directly from JWT
-    let username = token_data.claims.sub;
+    // Viewers may only watch shared terminals
+    claims.require(Role::Operator)?;
 
-    tracing::info!("WebSocket connection authorized for user: {}", username);
+    // Only hosts on the ssh.hosts allow-list (and the user's host restriction) may be targeted
+    let target = claims
+        .resolve_target(&state.config.get().ssh, params.host.as_deref())
+        .ok_or(StatusCode::FORBIDDEN)?;
 
-    let session_id = params.session_id.unwrap_or_else(|| "default".to_string());
+    tracing::info!(
+        "WebSocket connection authorized for user: {}",
+        claims.username()
+    );
 
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, username, session_id)))
+    let client = ClientInfo::from_request(&headers, peer, &state.config.get().server);
+
+    // Lasts as long as the terminal; its SSH connection and tmux commands are traced under it
+    let span = tracing::info_span!(
+        "terminal_bridge",
+        user = %claims.username(),
+        host = %target,
+        session_id = %session_id,
+    );
+    Ok(ws.on_upgrade(move |socket| {
+        handle_socket(socket, state, claims, client, target, session_id).instrument(span)
+    }))
 }
 
-async fn handle_socket(socket: WebSocket, username: String, session_id: String) {
+async fn handle_socket(
+    socket: WebSocket,
+    state: AppState,
+    claims: Claims,
+    client: ClientInfo,
+    target: SshTarget,
+    session_id: String,
+) {
+    // Get username directly from JWT
+    let family = claims.family();
+    let sso = claims.sso;
+    let username = claims.sub;
+
     tracing::info!(
-        "WebSocket connection established for user: {} (session: {})",
+        "WebSocket connection established for user: {} (host: {}, session: {})",
         username,
+        target,
         session_id
     );
 
     let (mut sender, mut receiver) = socket.split();
+    let config = state.config.get();
+
+    // Kept until the SSH connection is closed, so a shutdown waits for it
+    let Some(lease) = state.shutdown.track() else {
+        let reason = ExitReason::ServerShutdown;
+        let _ = sender.send(reason.frame()).await;
+        let _ = sender.send(reason.close_frame()).await;
+        return;
+    };
 
-    // Wait for password from first message (sent by frontend)
-    let password = match receiver.next().await {
-        Some(Ok(Message::Text(pwd))) => pwd.trim().to_string(),
-        Some(Ok(Message::Binary(data))) => String::from_utf8_lossy(&data).trim().to_string(),
-        _ => {
-            let _ = sender
-                .send(Message::Text("Error: No password received\r\n".to_string()))
-                .await;
+    // Counts against the per-user and per-host terminal limits until the socket closes
+    let _slot = match state.limits.acquire(&config.terminal, &username, &target) {
+        Ok(slot) => slot,
+        Err(e) => {
+            tracing::warn!("Terminal for {} on {} rejected: {}", username, target, e);
+            let reason = ExitReason::LimitExceeded {
+                message: e.to_string(),
+            };
+            let _ = sender.send(reason.frame()).await;
+            let _ = sender.send(reason.close_frame()).await;
+            return;
+        }
+    };
+
+    let auth = match ssh_credentials(&state, sso, &username, &mut receiver).await {
+        Ok(auth) => auth,
+        Err(ExitReason::ClientClosed) => return,
+        Err(reason) => {
+            let _ = sender.send(reason.frame()).await;
+            let _ = sender.send(reason.close_frame()).await;
             return;
         }
     };
@@ -81,209 +212,113 @@ async fn handle_socket(socket: WebSocket, username: String, session_id: String)
         .send(Message::Text("Connecting to SSH server...\r\n".to_string()))
         .await;
 
-    // Get SSH configuration
-    let ssh_host = std::env::var("SSH_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let ssh_port = std::env::var("SSH_PORT")
-        .unwrap_or_else(|_| "22".to_string())
-        .parse::<u16>()
-        .unwrap_or(22);
-
     // Establish SSH connection in blocking task
-    let (tx_to_ssh, mut rx_to_ssh) = mpsc::channel::<Vec<u8>>(100);
-    let (tx_from_ssh, mut rx_from_ssh) = mpsc::channel::<Vec<u8>>(100);
-    let (tx_resize, mut rx_resize) = mpsc::channel::<(u32, u32)>(10);
-
-    let username_clone = username.clone();
-    let password_clone = password.clone();
-    let session_id_clone = session_id.clone();
-
-    // Spawn SSH handling in blocking task
-    let ssh_task = tokio::task::spawn_blocking(move || {
-        // Connect to SSH server
-        let tcp = match TcpStream::connect((ssh_host.as_str(), ssh_port)) {
-            Ok(tcp) => tcp,
-            Err(e) => {
-                tracing::error!("Failed to connect to SSH server: {}", e);
-                return Err(format!("Failed to connect to SSH server: {}", e));
-            }
-        };
-
-        // Create SSH session
-        let mut session = match Session::new() {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!("Failed to create SSH session: {}", e);
-                return Err(format!("Failed to create SSH session: {}", e));
-            }
-        };
-
-        session.set_tcp_stream(tcp);
-        if let Err(e) = session.handshake() {
-            tracing::error!("SSH handshake failed: {}", e);
-            return Err(format!("SSH handshake failed: {}", e));
-        }
-
-        // Authenticate
-        if let Err(e) = session.userauth_password(&username_clone, &password_clone) {
-            tracing::error!("SSH authentication failed: {}", e);
-            return Err(format!("SSH authentication failed: {}", e));
-        }
-
-        if !session.authenticated() {
-            tracing::error!("SSH authentication failed");
-            return Err("SSH authentication failed".to_string());
-        }
-
-        // Request PTY and shell with tmux for session persistence
-        let mut channel = match session.channel_session() {
-            Ok(c) => c,
-            Err(e) => {
-                tracing::error!("Failed to open channel: {}", e);
-                return Err(format!("Failed to open channel: {}", e));
-            }
-        };
-
-        // Request PTY with initial size (80x24 is common default)
-        let initial_width = 80;
-        let initial_height = 24;
-        let pty_modes = ssh2::PtyModes::new();
-        if let Err(e) = channel.request_pty(
-            "xterm-256color",
-            Some(pty_modes),
-            Some((initial_width, initial_height, 0, 0)),
-        ) {
-            tracing::error!("Failed to request PTY: {}", e);
-            return Err(format!("Failed to request PTY: {}", e));
-        }
-
-        // Use tmux for persistent sessions
-        // Session name is provided by the frontend (session_id)
-        // Try to attach to existing session, or create new one if it doesn't exist
-        let tmux_command = format!(
-            "tmux attach-session -t '{}' || tmux new-session -s '{}'",
-            session_id_clone, session_id_clone
-        );
-
-        if let Err(e) = channel.exec(&tmux_command) {
-            tracing::error!("Failed to execute tmux command: {}", e);
-            return Err(format!("Failed to execute tmux command: {}", e));
-        }
-
-        // Now set to non-blocking mode for I/O
-        session.set_blocking(false);
-
-        tracing::info!("SSH shell started successfully");
-
-        // I/O loop
-        use std::io::{Read, Write};
-        let mut buffer = [0u8; 4096];
-        let mut stderr_buffer = [0u8; 4096];
+    let (tx_events, mut rx_events) = mpsc::channel::<ChannelEvent>(100);
+    let idle = IdlePolicy::new(&config.terminal.idle);
+    let connection = SshConnection::spawn(target.clone(), username.clone(), auth, idle, tx_events);
+
+    // Make the bridge reachable for broadcast input and share viewers while it is alive
+    let (bridge, rx_input) = BridgeHandle::new(&username, &target, &session_id, client);
+    let bridge_guard = state.bridges.register(bridge.clone());
+
+    // Request PTY with initial size (80x24 is common default)
+    let _ = connection
+        .commands
+        .send(ChannelCommand::Open {
+            channel: 0,
+            session_id: session_id.clone(),
+            cols: 80,
+            rows: 24,
+            input: rx_input,
+            stats: bridge.stats.clone(),
+            span: Span::current(),
+        })
+        .await;
 
+    // Task to read from SSH and send to WebSocket (and to any share viewers)
+    let tx_output = bridge.output.clone();
+    let mut rx_bridge_events = bridge.events.subscribe();
+    let mut rx_revocations = state.tokens.watch_revocation(family);
+    let closing = lease.closing();
+    let terminated = bridge.terminated();
+    let (tx_done, mut rx_done) = oneshot::channel::<()>();
+    let ssh_to_ws = async move {
+        // Dropped when this task ends, which stops the reader below
+        let _done = tx_done;
+        tokio::pin!(closing);
+        tokio::pin!(terminated);
         loop {
-            // Read from SSH stdout and send to WebSocket
-            match channel.read(&mut buffer) {
-                Ok(n) if n > 0 => {
-                    tracing::debug!("SSH stdout read {} bytes", n);
-                    if tx_from_ssh.blocking_send(buffer[..n].to_vec()).is_err() {
-                        tracing::error!("Failed to send stdout to WebSocket");
-                        break;
+            tokio::select! {
+                event = rx_events.recv() => match event {
+                    Some(ChannelEvent::Output { data, .. }) => {
+                        tracing::debug!("Sending {} bytes to WebSocket", data.len());
+                        let _ = tx_output.send(data.clone());
+                        if sender.send(Message::Binary(data)).await.is_err() {
+                            tracing::error!("Failed to send to WebSocket");
+                            break;
+                        }
                     }
-                }
-                Ok(_) => {}
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    // No data available, continue
-                }
-                Err(e) => {
-                    tracing::error!("SSH read error: {}", e);
-                    break;
-                }
-            }
-
-            // Read from SSH stderr and send to WebSocket
-            match channel.stderr().read(&mut stderr_buffer) {
-                Ok(n) if n > 0 => {
-                    tracing::debug!("SSH stderr read {} bytes", n);
-                    if tx_from_ssh
-                        .blocking_send(stderr_buffer[..n].to_vec())
-                        .is_err()
-                    {
-                        tracing::error!("Failed to send stderr to WebSocket");
+                    Some(ChannelEvent::Opened { .. }) => {}
+                    Some(ChannelEvent::IdleWarning { seconds_left, kind, .. }) => {
+                        let frame = idle.warning_frame(seconds_left, kind);
+                        if sender.send(Message::Text(frame.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(ChannelEvent::Closed { reason, .. }) => {
+                        // Tell the client why before closing with the matching code
+                        if !matches!(reason, ExitReason::ClientClosed) {
+                            tracing::info!("Terminal closed: {}", reason);
+                            let _ = sender.send(reason.frame()).await;
+                            let _ = sender.send(reason.close_frame()).await;
+                        }
                         break;
                     }
-                }
-                Ok(_) => {}
-                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    // No data available, continue
-                }
-                Err(e) => {
-                    tracing::error!("SSH stderr read error: {}", e);
-                    break;
-                }
-            }
-
-            // Read from WebSocket and send to SSH (non-blocking)
-            match rx_to_ssh.try_recv() {
-                Ok(data) => {
-                    if let Err(e) = channel.write_all(&data) {
-                        tracing::error!("SSH write error: {}", e);
+                    None => break,
+                },
+                Ok(event) = rx_bridge_events.recv() => {
+                    let frame = serde_json::to_string(&event).unwrap_or_default();
+                    if sender.send(Message::Text(frame)).await.is_err() {
+                        tracing::error!("Failed to send to WebSocket");
                         break;
                     }
-                    let _ = channel.flush();
                 }
-                Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
-                    // No data from WebSocket, continue
-                }
-                Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
-                    tracing::info!("WebSocket disconnected");
+                _ = rx_revocations.revoked() => {
+                    // The token this terminal was opened with has been logged out
+                    let reason = ExitReason::SessionRevoked;
+                    let _ = sender.send(reason.frame()).await;
+                    let _ = sender.send(reason.close_frame()).await;
                     break;
                 }
-            }
-
-            // Handle terminal resize
-            match rx_resize.try_recv() {
-                Ok((cols, rows)) => {
-                    tracing::info!("Resizing PTY to {}x{}", cols, rows);
-                    if let Err(e) = channel.request_pty_size(cols, rows, None, None) {
-                        tracing::error!("Failed to resize PTY: {}", e);
-                    }
-                }
-                Err(tokio::sync::mpsc::error::TryRecvError::Empty) => {
-                    // No resize command, continue
+                // The client did not detach within the shutdown grace period
+                _ = &mut closing => {
+                    let reason = ExitReason::ServerShutdown;
+                    let _ = sender.send(reason.frame()).await;
+                    let _ = sender.send(reason.close_frame()).await;
+                    break;
                 }
-                Err(tokio::sync::mpsc::error::TryRecvError::Disconnected) => {
-                    // Resize channel closed, but we can continue
+                // An administrator closed the connection
+                message = &mut terminated => {
+                    let reason = ExitReason::Terminated { message };
+                    let _ = sender.send(reason.frame()).await;
+                    let _ = sender.send(reason.close_frame()).await;
+                    break;
                 }
             }
-
-            std::thread::sleep(std::time::Duration::from_millis(10));
-
-            // Check if channel is EOF
-            if channel.eof() {
-                tracing::info!("SSH channel EOF");
-                break;
-            }
-        }
-
-        let _ = channel.close();
-        let _ = channel.wait_close();
-
-        Ok(())
-    });
-
-    // Task to read from SSH and send to WebSocket
-    let ssh_to_ws_task = tokio::spawn(async move {
-        while let Some(data) = rx_from_ssh.recv().await {
-            tracing::debug!("Sending {} bytes to WebSocket", data.len());
-            if sender.send(Message::Binary(data)).await.is_err() {
-                tracing::error!("Failed to send to WebSocket");
-                break;
-            }
         }
         tracing::info!("SSH to WebSocket task ended");
-    });
+    };
+    let ssh_to_ws_task = tokio::spawn(ssh_to_ws.in_current_span());
 
     // Read from WebSocket and send to SSH
-    while let Some(msg) = receiver.next().await {
+    loop {
+        let msg = tokio::select! {
+            msg = receiver.next() => msg,
+            // The SSH side is gone (exit, error or logout)
+            _ = &mut rx_done => break,
+        };
+        let Some(msg) = msg else { break };
+
         match msg {
             Ok(Message::Text(text)) => {
                 tracing::debug!("Received text from WebSocket: {} bytes", text.len());
@@ -293,21 +328,30 @@ async fn handle_socket(socket: WebSocket, username: String, session_id: String)
                     match terminal_msg {
                         TerminalMessage::Resize { cols, rows } => {
                             tracing::info!("Received resize command: {}x{}", cols, rows);
-                            if tx_resize.send((cols, rows)).await.is_err() {
+                            let resize = ChannelCommand::Resize {
+                                channel: 0,
+                                cols,
+                                rows,
+                            };
+                            if connection.commands.send(resize).await.is_err() {
                                 tracing::error!("Failed to send resize command");
                             }
                         }
+                        control => apply_control(&bridge, bridge.id, control),
                     }
-                } else {
+                } else if bridge.collab.holds_keyboard(bridge.id) {
                     // Not JSON, treat as terminal input
-                    if tx_to_ssh.send(text.as_bytes().to_vec()).await.is_err() {
+                    if bridge.input.send(text.as_bytes().to_vec()).await.is_err() {
                         break;
                     }
                 }
             }
             Ok(Message::Binary(data)) => {
                 tracing::debug!("Received binary from WebSocket: {} bytes", data.len());
-                if tx_to_ssh.send(data).await.is_err() {
+                if !bridge.collab.holds_keyboard(bridge.id) {
+                    continue;
+                }
+                if bridge.input.send(data).await.is_err() {
                     break;
                 }
             }
@@ -323,16 +367,20 @@ async fn handle_socket(socket: WebSocket, username: String, session_id: String)
         }
     }
 
-    // Clean up
-    drop(tx_to_ssh);
+    // Clean up: unregister first so viewers and broadcasts stop feeding the channel
+    drop(bridge_guard);
+    drop(bridge);
+    let SshConnection { commands, task } = connection;
+    drop(commands);
     tracing::info!("Waiting for SSH task to complete");
 
-    match ssh_task.await {
+    match task.await {
         Ok(Ok(())) => tracing::info!("SSH task completed successfully"),
         Ok(Err(e)) => tracing::error!("SSH task failed: {}", e),
         Err(e) => tracing::error!("SSH task join error: {}", e),
     }
 
     let _ = ssh_to_ws_task.await;
+    drop(lease);
     tracing::info!("WebSocket connection closed for user: {}", username);
 }
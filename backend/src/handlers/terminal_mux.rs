@@ -0,0 +1,592 @@
+use std::{collections::HashMap, future::Future, net::SocketAddr};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::Response,
+};
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::{
+    sync::mpsc::{self, error::TrySendError, OwnedPermit},
+    task::JoinHandle,
+};
+use tracing::{Instrument, Span};
+
+use super::terminal::{apply_control, ssh_credentials, TerminalMessage};
+use crate::{
+    auth::{rbac::Role, tickets::TicketScope},
+    middleware::auth::{websocket_claims, Claims},
+    ssh::{SshAuth, SshTarget},
+    state::AppState,
+    terminal::{
+        connection::{ChannelCommand, ChannelEvent, SshConnection},
+        exit::ExitReason,
+        idle::IdlePolicy,
+        is_valid_session_name,
+        limits::TerminalSlot,
+        registry::{BridgeGuard, BridgeHandle, ClientInfo},
+    },
+};
+
+#[derive(Deserialize)]
+pub struct MuxQuery {
+    /// One-time ticket from `POST /api/terminal-tickets`
+    ticket: Option<String>,
+}
+
+/// Control frame sent by the client; every frame names its logical channel.
+/// Input may also arrive as a binary frame: 4-byte big-endian channel id followed by the bytes.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MuxMessage {
+    Open {
+        channel: u32,
+        session_id: String,
+        host: Option<String>,
+        cols: Option<u32>,
+        rows: Option<u32>,
+    },
+    Input {
+        channel: u32,
+        data: String,
+    },
+    Resize {
+        channel: u32,
+        cols: u32,
+        rows: u32,
+    },
+    Close {
+        channel: u32,
+    },
+    RequestControl {
+        channel: u32,
+    },
+    GrantControl {
+        channel: u32,
+        to: String,
+    },
+    RevokeControl {
+        channel: u32,
+    },
+}
+
+/// Logical terminal carried by the multiplexed socket
+struct MuxChannel {
+    target: SshTarget,
+    bridge: BridgeHandle,
+    _guard: BridgeGuard,
+    _slot: TerminalSlot,
+    /// Forwards share/collaboration events for this channel
+    events_task: JoinHandle<()>,
+    /// Bridge span, ended once the worker has closed the channel as well
+    _span: Span,
+}
+
+/// Keystrokes that did not fit into their channel's input queue
+struct PendingInput {
+    input: mpsc::Sender<Vec<u8>>,
+    data: Vec<u8>,
+}
+
+/// Output frame: 4-byte big-endian channel id followed by the terminal bytes
+fn output_frame(channel: u32, data: &[u8]) -> Message {
+    let mut frame = Vec::with_capacity(4 + data.len());
+    frame.extend_from_slice(&channel.to_be_bytes());
+    frame.extend_from_slice(data);
+    Message::Binary(frame)
+}
+
+/// Split a binary input frame into channel id and payload
+fn parse_input_frame(frame: &[u8]) -> Option<(u32, &[u8])> {
+    let (id, data) = frame.split_first_chunk::<4>()?;
+    Some((u32::from_be_bytes(*id), data))
+}
+
+pub async fn websocket_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(params): Query<MuxQuery>,
+) -> Result<Response, StatusCode> {
+    state.origins.check_websocket(&headers)?;
+
+    let ticket = params.ticket.as_deref();
+    let (claims, ws) = websocket_claims(&state, &headers, ws, ticket, TicketScope::Mux)?;
+    claims.require(Role::Operator)?;
+
+    tracing::info!(
+        "Multiplexed WebSocket authorized for user: {}",
+        claims.username()
+    );
+
+    let client = ClientInfo::from_request(&headers, peer, &state.config.get().server);
+
+    let span = tracing::info_span!("terminal_mux", user = %claims.username());
+    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, claims, client).instrument(span)))
+}
+
+struct Mux {
+    state: AppState,
+    username: String,
+    /// Checked for the user's host restriction when channels are opened
+    claims: Claims,
+    auth: SshAuth,
+    client: ClientInfo,
+    out: mpsc::Sender<Message>,
+    events: mpsc::Sender<ChannelEvent>,
+    /// Channels an administrator closed, with the message for the client
+    terminated: mpsc::Sender<(u32, String)>,
+    /// One SSH connection per host, shared by all channels to that host
+    connections: HashMap<SshTarget, SshConnection>,
+    channels: HashMap<u32, MuxChannel>,
+    /// Input held back until its channel accepts it; the socket is not read meanwhile
+    pending: Option<PendingInput>,
+}
+
+async fn handle_socket(socket: WebSocket, state: AppState, claims: Claims, client: ClientInfo) {
+    let family = claims.family();
+    let username = claims.sub.clone();
+    let (mut sender, mut receiver) = socket.split();
+
+    // Kept until every SSH connection is closed, so a shutdown waits for them
+    let Some(lease) = state.shutdown.track() else {
+        let reason = ExitReason::ServerShutdown;
+        let _ = sender.send(reason.frame()).await;
+        let _ = sender.send(reason.close_frame()).await;
+        return;
+    };
+
+    let auth = match ssh_credentials(&state, claims.sso, &username, &mut receiver).await {
+        Ok(auth) => auth,
+        Err(ExitReason::ClientClosed) => return,
+        Err(reason) => {
+            let _ = sender.send(reason.frame()).await;
+            let _ = sender.send(reason.close_frame()).await;
+            return;
+        }
+    };
+
+    // All writers share one outgoing queue
+    let (out, mut rx_out) = mpsc::channel::<Message>(256);
+    let write = async move {
+        while let Some(msg) = rx_out.recv().await {
+            if sender.send(msg).await.is_err() {
+                tracing::error!("Failed to send to WebSocket");
+                break;
+            }
+        }
+    };
+    let writer = tokio::spawn(write.in_current_span());
+
+    let (events, mut rx_events) = mpsc::channel::<ChannelEvent>(256);
+    let (terminated, mut rx_terminated) = mpsc::channel::<(u32, String)>(16);
+    let mut revocations = state.tokens.watch_revocation(family);
+    let closing = lease.closing();
+    tokio::pin!(closing);
+    let mut mux = Mux {
+        state,
+        username,
+        claims,
+        auth,
+        client,
+        out,
+        events,
+        terminated,
+        connections: HashMap::new(),
+        channels: HashMap::new(),
+        pending: None,
+    };
+
+    loop {
+        tokio::select! {
+            // SSH workers block on output while their input queue is full, so the main
+            // loop must never wait for input space itself; it keeps draining events
+            // and stops reading the socket instead (backpressure on the client)
+            msg = receiver.next(), if mux.pending.is_none() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<MuxMessage>(&text) {
+                    Ok(msg) => mux.handle_message(msg).await,
+                    Err(e) => mux.send_error(None, &format!("Invalid frame: {}", e)).await,
+                },
+                Some(Ok(Message::Binary(frame))) => match parse_input_frame(&frame) {
+                    Some((channel, data)) => mux.send_input(channel, data.to_vec()).await,
+                    None => mux.send_error(None, "Binary frame without channel id").await,
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Err(e)) => {
+                    tracing::error!("WebSocket error: {}", e);
+                    break;
+                }
+                _ => {}
+            },
+            permit = mux.pending_permit(), if mux.pending.is_some() => mux.flush_pending(permit),
+            Some(event) = rx_events.recv() => mux.handle_event(event).await,
+            Some((channel, message)) = rx_terminated.recv() => {
+                let reason = ExitReason::Terminated { message };
+                mux.command(channel, ChannelCommand::Close { channel, reason }).await
+            }
+            _ = revocations.revoked() => {
+                let reason = ExitReason::SessionRevoked;
+                let _ = mux.out.send(reason.frame()).await;
+                let _ = mux.out.send(reason.close_frame()).await;
+                break;
+            }
+            // The client did not detach within the shutdown grace period
+            _ = &mut closing => {
+                let reason = ExitReason::ServerShutdown;
+                let _ = mux.out.send(reason.frame()).await;
+                let _ = mux.out.send(reason.close_frame()).await;
+                break;
+            }
+        }
+    }
+
+    // Workers must not block on a queue nobody reads anymore
+    drop(rx_events);
+    mux.shutdown().await;
+    let _ = writer.await;
+    drop(lease);
+}
+
+impl Mux {
+    async fn send_json(&self, frame: Value) {
+        let _ = self.out.send(Message::Text(frame.to_string())).await;
+    }
+
+    async fn send_error(&self, channel: Option<u32>, message: &str) {
+        self.send_json(json!({ "type": "error", "channel": channel, "message": message }))
+            .await;
+    }
+
+    async fn handle_message(&mut self, msg: MuxMessage) {
+        match msg {
+            MuxMessage::Open {
+                channel,
+                session_id,
+                host,
+                cols,
+                rows,
+            } => {
+                self.open(
+                    channel,
+                    session_id,
+                    host,
+                    cols.unwrap_or(80),
+                    rows.unwrap_or(24),
+                )
+                .await
+            }
+            MuxMessage::Input { channel, data } => {
+                self.send_input(channel, data.into_bytes()).await
+            }
+            MuxMessage::Resize {
+                channel,
+                cols,
+                rows,
+            } => {
+                self.command(
+                    channel,
+                    ChannelCommand::Resize {
+                        channel,
+                        cols,
+                        rows,
+                    },
+                )
+                .await
+            }
+            MuxMessage::Close { channel } => {
+                let reason = ExitReason::ClientClosed;
+                self.command(channel, ChannelCommand::Close { channel, reason })
+                    .await
+            }
+            MuxMessage::RequestControl { channel } => {
+                self.control(channel, TerminalMessage::RequestControl).await
+            }
+            MuxMessage::GrantControl { channel, to } => {
+                self.control(channel, TerminalMessage::GrantControl { to })
+                    .await
+            }
+            MuxMessage::RevokeControl { channel } => {
+                self.control(channel, TerminalMessage::RevokeControl).await
+            }
+        }
+    }
+
+    async fn open(
+        &mut self,
+        channel: u32,
+        session_id: String,
+        host: Option<String>,
+        cols: u32,
+        rows: u32,
+    ) {
+        if self.channels.contains_key(&channel) {
+            self.send_error(Some(channel), "Channel id already in use")
+                .await;
+            return;
+        }
+        if self.state.shutdown.is_draining() {
+            self.send_error(Some(channel), "Server is restarting, reconnect shortly")
+                .await;
+            return;
+        }
+        if !is_valid_session_name(&session_id) {
+            self.send_error(Some(channel), "Invalid session id").await;
+            return;
+        }
+
+        let config = self.state.config.get();
+        let Some(target) = self.claims.resolve_target(&config.ssh, host.as_deref()) else {
+            self.send_error(Some(channel), "Host is not allowed").await;
+            return;
+        };
+
+        let slot = match self
+            .state
+            .limits
+            .acquire(&config.terminal, &self.username, &target)
+        {
+            Ok(slot) => slot,
+            Err(e) => {
+                self.send_error(Some(channel), &e.to_string()).await;
+                return;
+            }
+        };
+
+        // Reuse the host's SSH connection unless it has already gone away
+        if self
+            .connections
+            .get(&target)
+            .is_some_and(|c| c.task.is_finished())
+        {
+            self.connections.remove(&target);
+        }
+        if !self.connections.contains_key(&target) {
+            // Certificates are short-lived, so every new connection gets its own
+            let auth = match &self.state.ssh_ca {
+                Some(ca) => match ca.issue(&self.username) {
+                    Ok(auth) => auth,
+                    Err(e) => {
+                        tracing::error!("Failed to issue SSH certificate: {:#}", e);
+                        self.send_error(Some(channel), "Failed to issue SSH certificate")
+                            .await;
+                        return;
+                    }
+                },
+                None => self.auth.clone(),
+            };
+
+            tracing::info!("Opening SSH connection to {} for {}", target, self.username);
+            let connection = SshConnection::spawn(
+                target.clone(),
+                self.username.clone(),
+                auth,
+                IdlePolicy::new(&config.terminal.idle),
+                self.events.clone(),
+            );
+            self.connections.insert(target.clone(), connection);
+        }
+        let connection = &self.connections[&target];
+
+        let (bridge, rx_input) =
+            BridgeHandle::new(&self.username, &target, &session_id, self.client.clone());
+        let span = tracing::info_span!(
+            "terminal_bridge",
+            user = %self.username,
+            host = %target,
+            session_id = %session_id,
+            channel,
+        );
+        let open = ChannelCommand::Open {
+            channel,
+            session_id,
+            cols,
+            rows,
+            input: rx_input,
+            stats: bridge.stats.clone(),
+            span: span.clone(),
+        };
+        // Never wait on the worker here, it may itself be waiting for events to drain
+        if let Err(e) = connection.commands.try_send(open) {
+            let message = match e {
+                TrySendError::Full(_) => "SSH connection is busy, try again",
+                TrySendError::Closed(_) => "SSH connection is closed",
+            };
+            self.send_error(Some(channel), message).await;
+            return;
+        }
+
+        // Share and collaboration events carry the channel id
+        let out = self.out.clone();
+        let mut rx_bridge_events = bridge.events.subscribe();
+        let terminated = bridge.terminated();
+        let tx_terminated = self.terminated.clone();
+        let events_task = tokio::spawn(async move {
+            tokio::pin!(terminated);
+            loop {
+                tokio::select! {
+                    Ok(event) = rx_bridge_events.recv() => {
+                        let mut frame = serde_json::to_value(&event).unwrap_or_default();
+                        frame["channel"] = json!(channel);
+                        if out.send(Message::Text(frame.to_string())).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Closed through the main loop, which owns the SSH connections
+                    message = &mut terminated => {
+                        let _ = tx_terminated.send((channel, message)).await;
+                        break;
+                    }
+                    else => break,
+                }
+            }
+        });
+
+        let guard = self.state.bridges.register(bridge.clone());
+        self.channels.insert(
+            channel,
+            MuxChannel {
+                target,
+                bridge,
+                _guard: guard,
+                _slot: slot,
+                events_task,
+                _span: span,
+            },
+        );
+    }
+
+    /// Queue keystrokes without waiting; a full queue holds them back in `pending`
+    async fn send_input(&mut self, channel: u32, data: Vec<u8>) {
+        let Some(mux_channel) = self.channels.get(&channel) else {
+            self.send_error(Some(channel), "Unknown channel").await;
+            return;
+        };
+
+        let bridge = &mux_channel.bridge;
+        if !bridge.collab.holds_keyboard(bridge.id) {
+            return;
+        }
+        match bridge.input.try_send(data) {
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(data)) => {
+                self.pending = Some(PendingInput {
+                    input: bridge.input.clone(),
+                    data,
+                });
+            }
+        }
+    }
+
+    /// Resolves once the held-back input fits, `None` when its channel has closed
+    fn pending_permit(&self) -> impl Future<Output = Option<OwnedPermit<Vec<u8>>>> + 'static {
+        let input = self.pending.as_ref().map(|p| p.input.clone());
+        async move {
+            match input {
+                Some(input) => input.reserve_owned().await.ok(),
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn flush_pending(&mut self, permit: Option<OwnedPermit<Vec<u8>>>) {
+        if let (Some(pending), Some(permit)) = (self.pending.take(), permit) {
+            permit.send(pending.data);
+        }
+    }
+
+    async fn command(&self, channel: u32, command: ChannelCommand) {
+        let connection = self
+            .channels
+            .get(&channel)
+            .and_then(|c| self.connections.get(&c.target));
+
+        match connection {
+            Some(connection) => {
+                if let Err(TrySendError::Full(_)) = connection.commands.try_send(command) {
+                    self.send_error(Some(channel), "SSH connection is busy, try again")
+                        .await;
+                }
+            }
+            None => self.send_error(Some(channel), "Unknown channel").await,
+        }
+    }
+
+    async fn control(&self, channel: u32, msg: TerminalMessage) {
+        match self.channels.get(&channel) {
+            Some(c) => apply_control(&c.bridge, c.bridge.id, msg),
+            None => self.send_error(Some(channel), "Unknown channel").await,
+        }
+    }
+
+    async fn handle_event(&mut self, event: ChannelEvent) {
+        match event {
+            ChannelEvent::Output { channel, data } => {
+                if let Some(c) = self.channels.get(&channel) {
+                    let _ = c.bridge.output.send(data.clone());
+                }
+                let _ = self.out.send(output_frame(channel, &data)).await;
+            }
+            ChannelEvent::Opened { channel } => {
+                self.send_json(json!({ "type": "opened", "channel": channel }))
+                    .await;
+            }
+            ChannelEvent::IdleWarning {
+                channel,
+                seconds_left,
+                kind,
+            } => {
+                let idle = IdlePolicy::new(&self.state.config.get().terminal.idle);
+                let mut frame = idle.warning_frame(seconds_left, kind);
+                frame["channel"] = json!(channel);
+                self.send_json(frame).await;
+            }
+            ChannelEvent::Closed { channel, reason } => {
+                if let Some(c) = self.channels.remove(&channel) {
+                    c.events_task.abort();
+                    self.release_connection(&c.target);
+                }
+                let mut frame = reason.details();
+                frame["type"] = json!("closed");
+                frame["channel"] = json!(channel);
+                self.send_json(frame).await;
+            }
+        }
+    }
+
+    /// Close the host's SSH connection once its last channel is gone; the next
+    /// channel to that host connects again
+    fn release_connection(&mut self, target: &SshTarget) {
+        if self.channels.values().any(|c| c.target == *target) {
+            return;
+        }
+        // The worker stops once its command queue is closed
+        if self.connections.remove(target).is_some() {
+            tracing::info!("Closed idle SSH connection to {}", target);
+        }
+    }
+
+    /// Unregister every channel, then let the SSH workers close their channels
+    async fn shutdown(self) {
+        for (_, c) in self.channels {
+            c.events_task.abort();
+        }
+
+        for (target, connection) in self.connections {
+            let SshConnection { commands, task } = connection;
+            drop(commands);
+            match task.await {
+                Ok(Ok(())) => tracing::info!("SSH connection to {} closed", target),
+                Ok(Err(e)) => tracing::error!("SSH connection to {} failed: {}", target, e),
+                Err(e) => tracing::error!("SSH task join error: {}", e),
+            }
+        }
+
+        tracing::info!("Multiplexed WebSocket closed for user: {}", self.username);
+    }
+}
@@ -1,61 +1,81 @@
 use axum::{
-    extract::Path,
+    extract::{Path, State},
     http::{HeaderMap, StatusCode},
-    Extension, Json,
+    response::{IntoResponse, Response},
+    Json,
 };
 use ssh2::Session;
-use std::net::TcpStream;
 
 use crate::{
-    middleware::auth::Claims,
-    models::{CreateTerminalSessionRequest, TerminalSessionResponse},
+    config::Config,
+    middleware::auth::{Claims, Operator},
+    models::{
+        CreateTerminalSessionRequest, HostUsageResponse, TerminalSessionResponse,
+        TerminalUsageResponse,
+    },
+    ssh::SshAuth,
+    state::AppState,
+    telemetry::{self, scrub::Secret},
     terminal::{
-        create_tmux_session_via_ssh, kill_tmux_session_via_ssh, list_tmux_sessions_via_ssh,
+        connection::connect, create_tmux_session_via_ssh, exit::ExitReason, is_valid_session_name,
+        kill_tmux_session_via_ssh, limits::SessionLimits, list_tmux_sessions_via_ssh,
+        set_tmux_keep_alive_via_ssh, tmux_session_exists_via_ssh,
     },
 };
 
+/// SSH credentials for a REST call: server-held credentials (CA certificate or
+/// SSO key) when available, otherwise the `X-SSH-Password` header
+fn ssh_auth(state: &AppState, claims: &Claims, headers: &HeaderMap) -> Result<SshAuth, StatusCode> {
+    let ssh = &state.config.get().ssh;
+
+    // tmux sessions live on the default host, which a restricted user may not be allowed
+    if !claims.may_use(&ssh.default_target()) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    match SshAuth::from_server(ssh, state.ssh_ca.as_deref(), claims.username(), claims.sso) {
+        Ok(Some(auth)) => return Ok(auth),
+        Ok(None) => {}
+        Err(e) => {
+            tracing::error!("No SSH credentials for {}: {:#}", claims.username(), e);
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+
+    headers
+        .get("X-SSH-Password")
+        .and_then(|v| v.to_str().ok())
+        .map(|password| SshAuth::Password(Secret::new(password)))
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
+
+/// Connect to the default host for a REST call
+fn open_session(config: &Config, username: &str, auth: &SshAuth) -> Result<Session, StatusCode> {
+    connect(&config.ssh.default_target(), username, auth).map_err(|e| {
+        tracing::error!("SSH connection failed: {}", e);
+        match e {
+            ExitReason::AuthFailed { .. } => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    })
+}
+
 // GET /api/terminal-sessions - Get all terminal sessions for the current user
-// Requires X-SSH-Password header to connect to SSH and list tmux sessions
+// Requires X-SSH-Password header (password logins) to connect to SSH and list tmux sessions
 pub async fn get_sessions(
-    Extension(claims): Extension<Claims>,
+    State(state): State<AppState>,
+    Operator(claims): Operator,
     headers: HeaderMap,
 ) -> Result<Json<Vec<TerminalSessionResponse>>, StatusCode> {
     // Get username directly from JWT
     let username = claims.username();
 
-    // Get password from header
-    let password = headers
-        .get("X-SSH-Password")
-        .and_then(|v| v.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+    // Server-held credentials, or the password sent in a header
+    let auth = ssh_auth(&state, &claims, &headers)?;
+    let config = state.config.get();
 
     // Connect to SSH and list tmux sessions
-    let ssh_host = std::env::var("SSH_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let ssh_port = std::env::var("SSH_PORT")
-        .unwrap_or_else(|_| "22".to_string())
-        .parse::<u16>()
-        .unwrap_or(22);
-
-    let tcp = TcpStream::connect((ssh_host.as_str(), ssh_port)).map_err(|e| {
-        tracing::error!("Failed to connect to SSH: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let mut session = Session::new().map_err(|e| {
-        tracing::error!("Failed to create SSH session: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    session.set_tcp_stream(tcp);
-    session.handshake().map_err(|e| {
-        tracing::error!("SSH handshake failed: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    session.userauth_password(username, password).map_err(|e| {
-        tracing::error!("SSH authentication failed: {}", e);
-        StatusCode::UNAUTHORIZED
-    })?;
+    let mut session = open_session(&config, username, &auth)?;
 
     // List tmux sessions
     let tmux_sessions = list_tmux_sessions_via_ssh(&mut session).map_err(|e| {
@@ -69,6 +89,7 @@ pub async fn get_sessions(
         .map(|s| TerminalSessionResponse {
             id: s.name.clone(),
             title: s.name,
+            keep_alive: s.keep_alive,
         })
         .collect();
 
@@ -76,66 +97,61 @@ pub async fn get_sessions(
 }
 
 // POST /api/terminal-sessions - Create a new terminal session
-// Requires X-SSH-Password header to connect to SSH and create tmux session
+// Requires X-SSH-Password header (password logins) to connect to SSH and create tmux session
 pub async fn create_session(
-    Extension(claims): Extension<Claims>,
+    State(state): State<AppState>,
+    Operator(claims): Operator,
     headers: HeaderMap,
     Json(req): Json<CreateTerminalSessionRequest>,
-) -> Result<Json<TerminalSessionResponse>, StatusCode> {
+) -> Result<Json<TerminalSessionResponse>, Response> {
+    // Session names end up in tmux commands on the SSH host
+    if !is_valid_session_name(&req.session_id) {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    }
+
     // Get username directly from JWT
     let username = claims.username();
 
-    // Get password from header
-    let password = headers
-        .get("X-SSH-Password")
-        .and_then(|v| v.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+    // Server-held credentials, or the password sent in a header
+    let auth = ssh_auth(&state, &claims, &headers).map_err(IntoResponse::into_response)?;
+    let config = state.config.get();
 
     // Connect to SSH
-    let ssh_host = std::env::var("SSH_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let ssh_port = std::env::var("SSH_PORT")
-        .unwrap_or_else(|_| "22".to_string())
-        .parse::<u16>()
-        .unwrap_or(22);
-
-    let tcp = TcpStream::connect((ssh_host.as_str(), ssh_port)).map_err(|e| {
-        tracing::error!("Failed to connect to SSH: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let mut session = Session::new().map_err(|e| {
-        tracing::error!("Failed to create SSH session: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    session.set_tcp_stream(tcp);
-    session.handshake().map_err(|e| {
-        tracing::error!("SSH handshake failed: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    session.userauth_password(username, password).map_err(|e| {
-        tracing::error!("SSH authentication failed: {}", e);
-        StatusCode::UNAUTHORIZED
-    })?;
+    let mut session =
+        open_session(&config, username, &auth).map_err(IntoResponse::into_response)?;
 
     // Check if session already exists, if not create it
     let session_exists =
         crate::terminal::tmux_session_exists_via_ssh(&mut session, &req.session_id).map_err(
             |e| {
                 tracing::error!("Failed to check tmux session: {}", e);
-                StatusCode::INTERNAL_SERVER_ERROR
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
             },
         )?;
 
     if !session_exists {
+        // New sessions count against terminal.max_tmux_sessions_per_user
+        if config.terminal.max_tmux_sessions_per_user.is_some() {
+            let existing = list_tmux_sessions_via_ssh(&mut session).map_err(|e| {
+                tracing::error!("Failed to list tmux sessions: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            })?;
+            SessionLimits::check_tmux(&config.terminal, existing.len()).map_err(|e| {
+                tracing::warn!("tmux session for {} rejected: {}", username, e);
+                e.into_response()
+            })?;
+        }
+
         // Create new tmux session
         create_tmux_session_via_ssh(&mut session, &req.session_id).map_err(|e| {
             tracing::error!("Failed to create tmux session: {}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         })?;
 
         tracing::info!("Created new tmux session: {}", req.session_id);
+        state
+            .bridges
+            .record_session_host(username, &config.ssh.default_target());
     } else {
         tracing::info!("Tmux session already exists: {}", req.session_id);
     }
@@ -143,52 +159,31 @@ pub async fn create_session(
     Ok(Json(TerminalSessionResponse {
         id: req.session_id.clone(),
         title: req.title,
+        keep_alive: false,
     }))
 }
 
 // DELETE /api/terminal-sessions/:session_id - Delete a terminal session
-// Requires X-SSH-Password header to connect to SSH and kill tmux session
+// Requires X-SSH-Password header (password logins) to connect to SSH and kill tmux session
 pub async fn delete_session(
-    Extension(claims): Extension<Claims>,
+    State(state): State<AppState>,
+    Operator(claims): Operator,
     headers: HeaderMap,
     Path(session_id): Path<String>,
 ) -> Result<StatusCode, StatusCode> {
+    if !is_valid_session_name(&session_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
     // Get username directly from JWT
     let username = claims.username();
 
-    // Get password from header
-    let password = headers
-        .get("X-SSH-Password")
-        .and_then(|v| v.to_str().ok())
-        .ok_or(StatusCode::UNAUTHORIZED)?;
+    // Server-held credentials, or the password sent in a header
+    let auth = ssh_auth(&state, &claims, &headers)?;
+    let config = state.config.get();
 
     // Connect to SSH
-    let ssh_host = std::env::var("SSH_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-    let ssh_port = std::env::var("SSH_PORT")
-        .unwrap_or_else(|_| "22".to_string())
-        .parse::<u16>()
-        .unwrap_or(22);
-
-    let tcp = TcpStream::connect((ssh_host.as_str(), ssh_port)).map_err(|e| {
-        tracing::error!("Failed to connect to SSH: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    let mut session = Session::new().map_err(|e| {
-        tracing::error!("Failed to create SSH session: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    session.set_tcp_stream(tcp);
-    session.handshake().map_err(|e| {
-        tracing::error!("SSH handshake failed: {}", e);
-        StatusCode::INTERNAL_SERVER_ERROR
-    })?;
-
-    session.userauth_password(username, password).map_err(|e| {
-        tracing::error!("SSH authentication failed: {}", e);
-        StatusCode::UNAUTHORIZED
-    })?;
+    let mut session = open_session(&config, username, &auth)?;
 
     // Check if session exists
     let session_exists = crate::terminal::tmux_session_exists_via_ssh(&mut session, &session_id)
@@ -212,3 +207,92 @@ pub async fn delete_session(
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+// PUT /api/terminal-sessions/:session_id/keep-alive - Pin a session against the idle reaper
+pub async fn pin_session(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+    headers: HeaderMap,
+    Path(session_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    set_keep_alive(&state, &claims, &headers, session_id, true).await
+}
+
+// DELETE /api/terminal-sessions/:session_id/keep-alive - Let the reaper clean the session up again
+pub async fn unpin_session(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+    headers: HeaderMap,
+    Path(session_id): Path<String>,
+) -> Result<StatusCode, StatusCode> {
+    set_keep_alive(&state, &claims, &headers, session_id, false).await
+}
+
+async fn set_keep_alive(
+    state: &AppState,
+    claims: &Claims,
+    headers: &HeaderMap,
+    session_id: String,
+    keep_alive: bool,
+) -> Result<StatusCode, StatusCode> {
+    if !is_valid_session_name(&session_id) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let auth = ssh_auth(state, claims, headers)?;
+    let username = claims.username().to_string();
+    let config = state.config.get();
+
+    telemetry::spawn_blocking(move || {
+        let mut session = open_session(&config, &username, &auth)?;
+
+        let exists = tmux_session_exists_via_ssh(&mut session, &session_id).map_err(|e| {
+            tracing::error!("Failed to check tmux session: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        if !exists {
+            return Err(StatusCode::NOT_FOUND);
+        }
+
+        set_tmux_keep_alive_via_ssh(&mut session, &session_id, keep_alive).map_err(|e| {
+            tracing::error!("Failed to update tmux session: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+        tracing::info!(
+            "User {} set keep-alive of tmux session {} to {}",
+            username,
+            session_id,
+            keep_alive
+        );
+        Ok(StatusCode::NO_CONTENT)
+    })
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+}
+
+// GET /api/terminal-usage - Open terminals of the current user against the configured limits
+pub async fn get_usage(
+    State(state): State<AppState>,
+    Operator(claims): Operator,
+) -> Json<TerminalUsageResponse> {
+    let limits = &state.config.get().terminal;
+    let hosts: Vec<HostUsageResponse> = state
+        .limits
+        .usage(claims.username())
+        .into_iter()
+        .map(|(target, used, total)| HostUsageResponse {
+            host: target.to_string(),
+            used,
+            total,
+            limit: limits.max_sessions_per_host,
+        })
+        .collect();
+
+    Json(TerminalUsageResponse {
+        used: hosts.iter().map(|h| h.used).sum(),
+        limit: limits.max_sessions_per_user,
+        tmux_limit: limits.max_tmux_sessions_per_user,
+        hosts,
+    })
+}
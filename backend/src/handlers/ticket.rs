@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use axum::{extract::State, http::StatusCode, Extension, Json};
+
+use crate::{
+    auth::{rbac::Role, tickets::TicketScope},
+    middleware::auth::Claims,
+    models::{CreateTerminalTicketRequest, TerminalTicketResponse, TicketSocket},
+    state::AppState,
+    terminal::is_valid_session_name,
+};
+
+// POST /api/terminal-tickets - One-time ticket for opening one terminal WebSocket
+pub async fn create_ticket(
+    State(state): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(req): Json<CreateTerminalTicketRequest>,
+) -> Result<Json<TerminalTicketResponse>, StatusCode> {
+    let config = state.config.get();
+
+    let scope = match req.socket {
+        TicketSocket::Terminal => {
+            claims.require(Role::Operator)?;
+            // Only hosts on the ssh.hosts allow-list (and the user's restriction) may be targeted
+            claims
+                .resolve_target(&config.ssh, req.host.as_deref())
+                .ok_or(StatusCode::FORBIDDEN)?;
+            let session_id = req
+                .session_id
+                .filter(|id| is_valid_session_name(id))
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            TicketScope::Terminal {
+                session_id,
+                host: req.host,
+            }
+        }
+        TicketSocket::Mux => {
+            claims.require(Role::Operator)?;
+            TicketScope::Mux
+        }
+        // Viewers may watch shared terminals
+        TicketSocket::Share => TicketScope::Share {
+            share: req.share.ok_or(StatusCode::BAD_REQUEST)?,
+        },
+        TicketSocket::Broadcast => {
+            claims.require(Role::Operator)?;
+            TicketScope::Broadcast {
+                group_id: req.group_id.ok_or(StatusCode::BAD_REQUEST)?,
+            }
+        }
+    };
+
+    let ttl = Duration::from_secs(config.terminal.ticket_ttl);
+    let ticket = state.tickets.issue(claims, scope, ttl);
+
+    Ok(Json(TerminalTicketResponse {
+        ticket,
+        expires_in: ttl.as_secs(),
+    }))
+}
@@ -1,19 +1,25 @@
+use anyhow::Context;
 use axum::{
     middleware as axum_middleware,
-    routing::{delete, get, post},
+    routing::{delete, get, post, put},
     Router,
 };
-use std::net::SocketAddr;
-use tower_http::{
-    cors::{Any, CorsLayer},
-    trace::TraceLayer,
-};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use config::Config;
+use state::AppState;
+use tokio::signal::unix::{signal, SignalKind};
+use tower_http::trace::TraceLayer;
+use tracing_subscriber::util::SubscriberInitExt;
 
 mod auth;
+mod config;
 mod handlers;
+mod metrics;
 mod middleware;
 mod models;
+mod server;
+mod ssh;
+mod state;
+mod telemetry;
 mod terminal;
 
 #[tokio::main]
@@ -21,16 +27,52 @@ async fn main() -> anyhow::Result<()> {
     // Load environment variables
     dotenvy::dotenv().ok();
 
+    // Settings from deuseda.toml and the environment; invalid values stop the server here.
+    // Only console logging until then, since span export is configured there too.
+    let config = {
+        let _console = tracing::subscriber::set_default(telemetry::subscriber(
+            None,
+            telemetry::LogFormat::from_env(),
+        ));
+        tracing::info!("Starting deuseda server (stateless mode - no database)");
+        Config::load().context("Invalid configuration")?
+    };
+
     // Initialize tracing
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "deuseda=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let tracer_provider = telemetry::tracer_provider(&config.telemetry)?;
+    telemetry::subscriber(tracer_provider.as_ref(), config.telemetry.log_format).init();
+    if let Some(endpoint) = &config.telemetry.otlp_endpoint {
+        tracing::info!("Exporting traces to {}", endpoint);
+    }
+    let tls = server::tls::TlsTerminator::load(&config.server.tls)?;
+    let listeners = server::Listener::bind_all(&config.server).await?;
+    let redirects = match (&tls, config.server.tls.http_redirect_port) {
+        (Some(_), Some(port)) => server::Listener::bind_redirects(&listeners, port)?,
+        _ => Vec::new(),
+    };
+
+    // Shared state (configuration, live terminal bridges, broadcast groups)
+    let state = AppState::new(config)?;
+
+    // Background cleanup of abandoned tmux sessions ([reaper] max_idle)
+    terminal::reaper::TmuxReaper::spawn(state.clone());
 
-    tracing::info!("Starting deuseda server (stateless mode - no database)");
+    // SIGHUP re-reads the configuration without dropping connections
+    let live_config = state.config.clone();
+    let broadcasts = state.broadcasts.clone();
+    let mut hangup = signal(SignalKind::hangup())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Err(e) = live_config.reload() {
+                tracing::error!("Configuration reload failed, keeping the old one: {:#}", e);
+            }
+            // Turning broadcasting off also ends the groups already running
+            if !live_config.get().terminal.broadcast_enabled {
+                broadcasts.stop_all();
+            }
+        }
+    });
 
     // Protected routes (require authentication)
     let protected_routes = Router::new()
@@ -46,35 +88,134 @@ async fn main() -> anyhow::Result<()> {
             "/api/terminal-sessions/:session_id",
             delete(handlers::terminal_session::delete_session),
         )
-        .route_layer(axum_middleware::from_fn(middleware::auth_middleware));
+        .route(
+            "/api/terminal-sessions/:session_id/keep-alive",
+            put(handlers::terminal_session::pin_session),
+        )
+        .route(
+            "/api/terminal-sessions/:session_id/keep-alive",
+            delete(handlers::terminal_session::unpin_session),
+        )
+        .route(
+            "/api/terminal-usage",
+            get(handlers::terminal_session::get_usage),
+        )
+        .route(
+            "/api/broadcast-groups",
+            get(handlers::broadcast::list_groups),
+        )
+        .route(
+            "/api/broadcast-groups",
+            post(handlers::broadcast::create_group),
+        )
+        .route(
+            "/api/broadcast-groups/:group_id",
+            delete(handlers::broadcast::delete_group),
+        )
+        .route("/api/terminal-shares", get(handlers::share::list_shares))
+        .route("/api/terminal-shares", post(handlers::share::create_share))
+        .route(
+            "/api/terminal-shares/:token",
+            delete(handlers::share::revoke_share),
+        )
+        .route(
+            "/api/terminal-tickets",
+            post(handlers::ticket::create_ticket),
+        )
+        .route("/api/auth/logout", post(handlers::auth::logout))
+        .route("/api/admin/rbac", get(handlers::admin::rbac_policy))
+        .route(
+            "/api/admin/connections",
+            get(handlers::admin::list_connections),
+        )
+        .route(
+            "/api/admin/connections/:id",
+            delete(handlers::admin::terminate_connection),
+        )
+        .route(
+            "/api/admin/users/:username/connections",
+            delete(handlers::admin::terminate_user_connections),
+        )
+        .route(
+            "/api/admin/broadcast-groups",
+            get(handlers::admin::list_broadcast_groups),
+        )
+        .route(
+            "/api/admin/broadcast-groups/:group_id",
+            delete(handlers::admin::stop_broadcast_group),
+        )
+        .route_layer(axum_middleware::from_fn_with_state(
+            state.clone(),
+            middleware::auth_middleware,
+        ));
 
     // Build application routes
     let app = Router::new()
-        .route("/health", get(handlers::health_check))
+        .route("/health", get(handlers::health::live))
+        .route("/health/live", get(handlers::health::live))
+        .route("/health/ready", get(handlers::health::ready))
+        .route("/metrics", get(handlers::metrics::scrape))
         .route("/api/auth/login", post(handlers::auth::login))
+        .route("/api/auth/refresh", post(handlers::auth::refresh))
+        .route("/api/auth/providers", get(handlers::auth::providers))
+        .route("/api/auth/oidc/login", get(handlers::auth::oidc_login))
+        .route(
+            "/api/auth/oidc/callback",
+            get(handlers::auth::oidc_callback),
+        )
+        .route("/.well-known/jwks.json", get(handlers::auth::jwks))
         .route("/ws/terminal", get(handlers::terminal::websocket_handler))
-        .merge(protected_routes)
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
+        .route(
+            "/ws/terminal/mux",
+            get(handlers::terminal_mux::websocket_handler),
         )
-        .layer(TraceLayer::new_for_http());
+        .route(
+            "/ws/terminal/share",
+            get(handlers::share::websocket_handler),
+        )
+        .route("/ws/broadcast", get(handlers::broadcast::websocket_handler))
+        .merge(protected_routes)
+        .layer(state.origins.cors_layer())
+        .layer(TraceLayer::new_for_http().make_span_with(telemetry::http_span))
+        .layer(axum_middleware::from_fn(middleware::request_id))
+        .layer(axum_middleware::from_fn(middleware::track_http))
+        .with_state(state.clone());
+
+    // HTTP to HTTPS redirect on `server.tls.http_redirect_port`
+    let shutdown = state.shutdown.clone();
+    if let Some(https_port) = server::Listener::tcp_port(&listeners) {
+        if !redirects.is_empty() {
+            let redirect_app = server::tls::redirect_app(https_port);
+            tokio::spawn(server::serve(
+                redirects,
+                redirect_app,
+                None,
+                shutdown.clone(),
+            ));
+        }
+    }
+    if let Some(tls) = &tls {
+        tls.clone().watch();
+    }
 
-    // Get server address from environment
-    let _host = std::env::var("SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-    let port = std::env::var("SERVER_PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(8080);
+    let server = tokio::spawn(server::serve(listeners, app, tls, shutdown.clone()));
 
-    // Bind to all interfaces (0.0.0.0)
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
-    tracing::info!("Listening on {}", addr);
+    // SIGTERM (every rollout) or Ctrl-C: drain terminals instead of cutting them off
+    tokio::select! {
+        _ = terminate.recv() => tracing::info!("SIGTERM received"),
+        _ = tokio::signal::ctrl_c() => tracing::info!("Interrupted"),
+    }
+    shutdown.run(&state.bridges).await;
+    let _ = server.await;
+    tracing::info!("Shutdown complete");
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    // Send the spans still buffered; the exporter blocks
+    if let Some(provider) = tracer_provider {
+        let flushed = tokio::task::spawn_blocking(move || provider.shutdown()).await;
+        if let Ok(Err(e)) = flushed {
+            tracing::warn!("Failed to flush traces: {}", e);
+        }
+    }
 
     Ok(())
 }
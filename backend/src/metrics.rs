@@ -0,0 +1,217 @@
+use std::{sync::LazyLock, time::Instant};
+
+use prometheus::{
+    core::Collector, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Process-wide metrics, served at `/metrics` in the Prometheus text format
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+pub struct Metrics {
+    registry: Registry,
+    /// Login attempts by method (`password`, `sso`) and outcome (`success`, `failure`, `throttled`)
+    logins: IntCounterVec,
+    /// TCP connect to an SSH server, by `host:port`
+    ssh_connect: HistogramVec,
+    /// SSH protocol handshake after the TCP connect, by `host:port`
+    ssh_handshake: HistogramVec,
+    /// Failed connects and handshakes by `host:port` and stage
+    ssh_failures: IntCounterVec,
+    /// Open terminal bridges, set when scraped
+    pub terminals: IntGauge,
+    /// Terminal traffic by direction (`in` = keystrokes, `out` = output)
+    terminal_bytes: IntCounterVec,
+    /// `*_via_ssh` tmux commands by function
+    tmux_commands: HistogramVec,
+    tmux_failures: IntCounterVec,
+    /// HTTP requests by method, matched route and status
+    http_requests: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry =
+            Registry::new_custom(Some("deuseda".to_string()), None).expect("valid metrics prefix");
+
+        Self {
+            logins: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "login_attempts_total",
+                        "Login attempts by method and outcome",
+                    ),
+                    &["method", "outcome"],
+                ),
+            ),
+            ssh_connect: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "ssh_connect_duration_seconds",
+                        "TCP connect to an SSH server",
+                    ),
+                    &["host"],
+                ),
+            ),
+            ssh_handshake: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "ssh_handshake_duration_seconds",
+                        "SSH handshake after the TCP connect",
+                    ),
+                    &["host"],
+                ),
+            ),
+            ssh_failures: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "ssh_connect_failures_total",
+                        "Failed SSH connects (stage connect) and handshakes (stage handshake)",
+                    ),
+                    &["host", "stage"],
+                ),
+            ),
+            terminals: register(
+                &registry,
+                IntGauge::new("terminals_active", "Open terminal bridges"),
+            ),
+            terminal_bytes: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "terminal_bytes_total",
+                        "Terminal bytes written to (in) and read from (out) SSH channels",
+                    ),
+                    &["direction"],
+                ),
+            ),
+            tmux_commands: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "tmux_command_duration_seconds",
+                        "tmux commands run over SSH",
+                    ),
+                    &["function"],
+                ),
+            ),
+            tmux_failures: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("tmux_command_failures_total", "tmux commands that failed"),
+                    &["function"],
+                ),
+            ),
+            http_requests: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("http_request_duration_seconds", "HTTP request durations"),
+                    &["method", "route", "status"],
+                ),
+            ),
+            registry,
+        }
+    }
+
+    pub fn login(&self, method: &str, outcome: &str) {
+        self.logins.with_label_values(&[method, outcome]).inc();
+    }
+
+    /// Time the TCP connect to `host`, counting failures
+    pub fn ssh_connect<T, E>(
+        &self,
+        host: &str,
+        connect: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        self.time_ssh(&self.ssh_connect, "connect", host, connect)
+    }
+
+    /// Time the SSH handshake with `host`, counting failures
+    pub fn ssh_handshake<T, E>(
+        &self,
+        host: &str,
+        handshake: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        self.time_ssh(&self.ssh_handshake, "handshake", host, handshake)
+    }
+
+    fn time_ssh<T, E>(
+        &self,
+        histogram: &HistogramVec,
+        stage: &str,
+        host: &str,
+        step: impl FnOnce() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let started = Instant::now();
+        let result = step();
+        histogram
+            .with_label_values(&[host])
+            .observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            self.ssh_failures.with_label_values(&[host, stage]).inc();
+        }
+        result
+    }
+
+    /// Run the body of a `*_via_ssh` function, recording its latency and failures
+    pub fn tmux<T>(
+        &self,
+        function: &str,
+        command: impl FnOnce() -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let started = Instant::now();
+        let result = command();
+        self.tmux_commands
+            .with_label_values(&[function])
+            .observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            self.tmux_failures.with_label_values(&[function]).inc();
+        }
+        result
+    }
+
+    pub fn terminal_input(&self, bytes: usize) {
+        self.terminal_bytes
+            .with_label_values(&["in"])
+            .inc_by(bytes as u64);
+    }
+
+    pub fn terminal_output(&self, bytes: usize) {
+        self.terminal_bytes
+            .with_label_values(&["out"])
+            .inc_by(bytes as u64);
+    }
+
+    pub fn http_request(&self, method: &str, route: &str, status: u16, started: Instant) {
+        self.http_requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    /// Everything in the Prometheus text exposition format
+    pub fn encode(&self) -> String {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .unwrap_or_default()
+    }
+}
+
+fn register<M: Collector + Clone + 'static>(
+    registry: &Registry,
+    metric: prometheus::Result<M>,
+) -> M {
+    // Names and labels are fixed above, so this only fails on a programming error
+    let metric = metric.expect("valid metric");
+    registry
+        .register(Box::new(metric.clone()))
+        .expect("unique metric name");
+    metric
+}
@@ -1,26 +1,176 @@
 use axum::{
-    extract::Request,
-    http::{header, StatusCode},
+    async_trait,
+    extract::{ws::WebSocketUpgrade, FromRequestParts, Request, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
-use jsonwebtoken::{decode, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    auth::{rbac::Role, tickets::TicketScope, tokens::TokenStore},
+    config::SshConfig,
+    ssh::SshTarget,
+    state::AppState,
+};
 
 /// JWT Claims with username as subject
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Claims {
     pub sub: String, // username (not user_id anymore)
     pub exp: usize,
+    pub iat: usize,
+    /// Unique token id, checked against the revocation list
+    pub jti: String,
+    /// Refresh family the token was issued from
+    pub fam: String,
+    /// Logged in through the identity provider; SSH uses the user's key instead of a password
+    #[serde(default)]
+    pub sso: bool,
+    #[serde(default)]
+    pub role: Role,
+    /// Hosts the user is restricted to; absent means every allowed host
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hosts: Option<Vec<String>>,
 }
 
 impl Claims {
     pub fn username(&self) -> &str {
         &self.sub
     }
+
+    pub fn family(&self) -> Option<Uuid> {
+        self.fam.parse().ok()
+    }
+
+    /// Whether the user's host restriction (if any) includes `target`
+    pub fn may_use(&self, target: &SshTarget) -> bool {
+        self.hosts.as_ref().is_none_or(|hosts| {
+            hosts
+                .iter()
+                .any(|h| SshTarget::parse(h).as_ref() == Some(target))
+        })
+    }
+
+    /// Resolve a requested host against the allow-list and the user's host restriction
+    pub fn resolve_target(&self, ssh: &SshConfig, requested: Option<&str>) -> Option<SshTarget> {
+        ssh.resolve(requested).filter(|target| self.may_use(target))
+    }
+
+    /// Reject users below `role` with 403
+    pub fn require(&self, role: Role) -> Result<(), StatusCode> {
+        if self.role >= role {
+            Ok(())
+        } else {
+            tracing::warn!(
+                "User {} ({:?}) denied access requiring {:?}",
+                self.sub,
+                self.role,
+                role
+            );
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+/// Claims set by `auth_middleware`, checked against a minimum role
+fn claims_with_role(parts: &Parts, role: Role) -> Result<Claims, StatusCode> {
+    let claims = parts
+        .extensions
+        .get::<Claims>()
+        .cloned()
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    claims.require(role)?;
+    Ok(claims)
+}
+
+/// Extractor for routes that open or control terminals; viewers are rejected
+pub struct Operator(pub Claims);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Operator {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        claims_with_role(parts, Role::Operator).map(Self)
+    }
 }
 
-pub async fn auth_middleware(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+/// Extractor for the admin APIs
+pub struct Admin(pub Claims);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Admin {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        claims_with_role(parts, Role::Admin).map(Self)
+    }
+}
+
+/// Validate a JWT, reject revoked tokens and return its claims
+pub fn verify_token(tokens: &TokenStore, token: &str) -> Result<Claims, StatusCode> {
+    let claims = tokens.keys().verify(token).map_err(|e| {
+        tracing::debug!("Rejected access token: {}", e);
+        StatusCode::UNAUTHORIZED
+    })?;
+
+    if tokens.is_revoked(&claims) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Ok(claims)
+}
+
+/// Access token offered as `Sec-WebSocket-Protocol: bearer, <token>`
+/// (browsers cannot set an `Authorization` header on WebSockets)
+fn bearer_protocol(headers: &HeaderMap) -> Option<&str> {
+    let mut protocols = headers
+        .get(header::SEC_WEBSOCKET_PROTOCOL)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim);
+
+    (protocols.next()? == "bearer").then(|| protocols.next())?
+}
+
+/// Authenticate a WebSocket upgrade with a one-time ticket for `scope` (`?ticket=`) or the
+/// `bearer` subprotocol. The access token is never accepted in the query string: it would
+/// end up in access logs.
+pub fn websocket_claims(
+    state: &AppState,
+    headers: &HeaderMap,
+    ws: WebSocketUpgrade,
+    ticket: Option<&str>,
+    scope: TicketScope,
+) -> Result<(Claims, WebSocketUpgrade), StatusCode> {
+    match (ticket, bearer_protocol(headers)) {
+        (Some(ticket), _) => {
+            let claims = state
+                .tickets
+                .redeem(ticket, &scope)
+                .ok_or(StatusCode::UNAUTHORIZED)?;
+            // The session may have been logged out since the ticket was issued
+            if state.tokens.is_revoked(&claims) {
+                return Err(StatusCode::UNAUTHORIZED);
+            }
+            Ok((claims, ws))
+        }
+        (None, Some(token)) => Ok((
+            verify_token(&state.tokens, token)?,
+            ws.protocols(["bearer"]),
+        )),
+        (None, None) => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+pub async fn auth_middleware(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
     let auth_header = req
         .headers()
         .get(header::AUTHORIZATION)
@@ -29,18 +179,10 @@ pub async fn auth_middleware(mut req: Request, next: Next) -> Result<Response, S
 
     let token = auth_header.strip_prefix("Bearer ").unwrap_or(auth_header);
 
-    let secret = std::env::var("JWT_SECRET")
-        .expect("JWT_SECRET environment variable must be set for production use");
-
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|_| StatusCode::UNAUTHORIZED)?;
+    let claims = verify_token(&state.tokens, token)?;
 
     // Insert Claims with username
-    req.extensions_mut().insert(token_data.claims);
+    req.extensions_mut().insert(claims);
 
     Ok(next.run(req).await)
 }
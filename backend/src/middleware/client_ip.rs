@@ -0,0 +1,106 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::http::HeaderMap;
+
+use crate::config::ServerConfig;
+
+/// Client address for throttling and audit logs. `X-Forwarded-For` is only read when the
+/// direct peer is one of `server.trusted_proxies` (and `server.trust_proxy_headers` is on).
+/// Every proxy appends the address it received the request from, so the list is walked
+/// from the right and the first entry that is not a trusted proxy is the client; anything
+/// left of it was written by the client and may be forged.
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, server: &ServerConfig) -> IpAddr {
+    // Dual-stack listeners report IPv4 clients as `::ffff:a.b.c.d`
+    let peer_ip = peer.ip().to_canonical();
+    if !server.trust_proxy_headers {
+        return peer_ip;
+    }
+
+    let proxies = server.trusted_proxies().unwrap_or_default();
+    let trusted = |ip: &IpAddr| proxies.iter().any(|net| net.contains(ip));
+    if !trusted(&peer_ip) {
+        return peer_ip;
+    }
+
+    let mut client = peer_ip;
+    let forwarded = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect::<Vec<_>>();
+    for entry in forwarded.into_iter().rev() {
+        match entry.trim().parse::<IpAddr>() {
+            Ok(ip) => client = ip.to_canonical(),
+            // Garbage from the client; stop at the last address a proxy vouched for
+            Err(_) => break,
+        }
+        if !trusted(&client) {
+            break;
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn server(trust: bool, proxies: &[&str]) -> ServerConfig {
+        ServerConfig {
+            trust_proxy_headers: trust,
+            trusted_proxies: proxies.iter().map(|p| p.to_string()).collect(),
+            ..ServerConfig::default()
+        }
+    }
+
+    fn ip(headers: &[&str], peer: &str, server: &ServerConfig) -> String {
+        let mut map = HeaderMap::new();
+        for value in headers {
+            map.append("X-Forwarded-For", HeaderValue::from_str(value).unwrap());
+        }
+        client_ip(&map, peer.parse().unwrap(), server).to_string()
+    }
+
+    #[test]
+    fn header_ignored_unless_trusted() {
+        let off = server(false, &["10.0.0.0/8"]);
+        assert_eq!(ip(&["1.2.3.4"], "10.0.0.1:80", &off), "10.0.0.1");
+
+        // A client talking to us directly cannot claim another address
+        let on = server(true, &["10.0.0.0/8"]);
+        assert_eq!(ip(&["1.2.3.4"], "203.0.113.9:80", &on), "203.0.113.9");
+    }
+
+    #[test]
+    fn right_most_untrusted_entry_is_the_client() {
+        let on = server(true, &["10.0.0.0/8"]);
+        assert_eq!(ip(&["1.2.3.4"], "10.0.0.1:80", &on), "1.2.3.4");
+        // The client prepended a forged address; our proxy appended the real one
+        assert_eq!(ip(&["6.6.6.6, 1.2.3.4"], "10.0.0.1:80", &on), "1.2.3.4");
+        // Chained proxies are skipped, also across repeated headers
+        assert_eq!(
+            ip(&["6.6.6.6, 1.2.3.4", "10.1.1.1"], "10.0.0.1:80", &on),
+            "1.2.3.4"
+        );
+    }
+
+    #[test]
+    fn garbage_stops_at_the_last_vouched_address() {
+        let on = server(true, &["10.0.0.0/8"]);
+        assert_eq!(ip(&["not-an-ip, 10.2.2.2"], "10.0.0.1:80", &on), "10.2.2.2");
+        assert_eq!(ip(&[], "10.0.0.1:80", &on), "10.0.0.1");
+    }
+
+    #[test]
+    fn addresses_are_canonical() {
+        let on = server(true, &["127.0.0.1"]);
+        assert_eq!(
+            ip(&["::ffff:1.2.3.4"], "[::ffff:127.0.0.1]:80", &on),
+            "1.2.3.4"
+        );
+        assert_eq!(ip(&["2001:db8::1"], "127.0.0.1:80", &on), "2001:db8::1");
+    }
+}
@@ -1,3 +1,10 @@
 pub mod auth;
+pub mod client_ip;
+pub mod metrics;
+pub mod origin;
+pub mod request_id;
 
 pub use auth::auth_middleware;
+pub use client_ip::client_ip;
+pub use metrics::track_http;
+pub use request_id::request_id;
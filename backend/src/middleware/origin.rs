@@ -0,0 +1,73 @@
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use super::request_id::X_REQUEST_ID;
+
+/// Browser origins allowed to call the API and open terminal WebSockets,
+/// from `server.cors_allowed_origins` (`*` allows any origin)
+#[derive(Debug, Clone)]
+pub struct AllowedOrigins {
+    /// `None` allows every origin
+    origins: Option<Vec<HeaderValue>>,
+}
+
+impl AllowedOrigins {
+    pub fn new(configured: &[String]) -> Self {
+        if configured.iter().any(|o| o.trim() == "*") {
+            tracing::warn!("CORS allowed origin `*` lets any website use the API");
+            return Self { origins: None };
+        }
+
+        let origins = configured
+            .iter()
+            .map(|o| o.trim().trim_end_matches('/'))
+            .filter(|o| !o.is_empty())
+            .filter_map(|o| match HeaderValue::from_str(o) {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    tracing::warn!("Ignoring invalid CORS allowed origin: {}", o);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            origins: Some(origins),
+        }
+    }
+
+    pub fn cors_layer(&self) -> CorsLayer {
+        let allow_origin = match &self.origins {
+            Some(origins) => AllowOrigin::list(origins.clone()),
+            None => AllowOrigin::any(),
+        };
+
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                HeaderName::from_static("x-ssh-password"),
+            ])
+            .expose_headers([header::RETRY_AFTER, X_REQUEST_ID])
+    }
+
+    /// Reject WebSocket upgrades from pages on other origins (cross-site WebSocket hijacking).
+    /// Clients that send no `Origin` are not browsers and are let through.
+    pub fn check_websocket(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let (Some(origins), Some(origin)) = (&self.origins, headers.get(header::ORIGIN)) else {
+            return Ok(());
+        };
+
+        if origins.contains(origin) {
+            Ok(())
+        } else {
+            tracing::warn!(
+                "Rejected WebSocket upgrade from origin {:?}",
+                origin.to_str().unwrap_or("<invalid>")
+            );
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
@@ -1,5 +1,10 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    auth::rbac::Role, ssh::probe::ProbeResult, telemetry::scrub::Secret,
+    terminal::share::ShareScope,
+};
+
 // No database models needed - all data comes from SSH/tmux
 
 #[derive(Debug, Deserialize)]
@@ -12,16 +17,186 @@ pub struct CreateTerminalSessionRequest {
 pub struct TerminalSessionResponse {
     pub id: String,
     pub title: String,
+    /// Pinned against the idle session reaper
+    pub keep_alive: bool,
+}
+
+/// Open terminals of the current user; limits are `None` when unlimited
+#[derive(Debug, Serialize)]
+pub struct TerminalUsageResponse {
+    pub used: usize,
+    pub limit: Option<usize>,
+    pub tmux_limit: Option<usize>,
+    pub hosts: Vec<HostUsageResponse>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostUsageResponse {
+    pub host: String,
+    /// Terminals of the current user on this host
+    pub used: usize,
+    /// Terminals of all users on this host, counted against `limit`
+    pub total: usize,
+    pub limit: Option<usize>,
+}
+
+/// WebSocket a ticket is requested for
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TicketSocket {
+    /// `/ws/terminal`; needs `session_id`
+    #[default]
+    Terminal,
+    /// `/ws/terminal/mux`
+    Mux,
+    /// `/ws/terminal/share`; needs `share`
+    Share,
+    /// `/ws/broadcast`; needs `group_id`
+    Broadcast,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTerminalTicketRequest {
+    #[serde(default)]
+    pub socket: TicketSocket,
+    pub session_id: Option<String>,
+    pub host: Option<String>,
+    /// Share link token
+    pub share: Option<String>,
+    pub group_id: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TerminalTicketResponse {
+    pub ticket: String,
+    /// Seconds until the ticket can no longer be redeemed
+    pub expires_in: u64,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
     pub username: String,
-    pub password: String,
+    pub password: Secret,
 }
 
 #[derive(Debug, Serialize)]
 pub struct LoginResponse {
     pub token: String,
+    pub refresh_token: String,
+    /// Access token lifetime in seconds
+    pub expires_in: i64,
     pub username: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Query of the identity provider's redirect back to `/api/auth/oidc/callback`
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthProvidersResponse {
+    pub password: bool,
+    pub oidc: bool,
+    /// SSH uses CA certificates, so the browser must not keep or send the password
+    pub ssh_certificates: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BroadcastTargetRequest {
+    pub session_id: String,
+    pub host: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateBroadcastGroupRequest {
+    pub targets: Vec<BroadcastTargetRequest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BroadcastTargetResponse {
+    pub host: String,
+    pub session_id: String,
+    pub connected: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BroadcastGroupResponse {
+    pub id: String,
+    pub owner: String,
+    pub targets: Vec<BroadcastTargetResponse>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareRequest {
+    pub session_id: String,
+    pub host: Option<String>,
+    pub scope: Option<ShareScope>,
+    /// Lifetime in seconds
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShareResponse {
+    pub token: String,
+    pub session_id: String,
+    pub scope: ShareScope,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// `status` is `ok`, `degraded` (an optional dependency is down) or `unavailable`.
+/// `checks` are only shown to admins and scrapers holding the metrics token.
+#[derive(Debug, Serialize)]
+pub struct ReadinessResponse {
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checks: Option<Vec<DependencyCheck>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DependencyCheck {
+    pub name: &'static str,
+    pub target: String,
+    /// The instance is unready while a required dependency is down
+    pub required: bool,
+    #[serde(flatten)]
+    pub result: ProbeResult,
+}
+
+/// Open terminal as listed by `/api/admin/connections`
+#[derive(Debug, Serialize)]
+pub struct ConnectionResponse {
+    pub id: uuid::Uuid,
+    pub username: String,
+    pub host: String,
+    /// tmux session
+    pub session_id: String,
+    pub client_ip: String,
+    pub user_agent: Option<String>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    /// Keystrokes sent to the SSH channel
+    pub bytes_in: u64,
+    /// Output read from the SSH channel
+    pub bytes_out: u64,
+    pub last_activity: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TerminateQuery {
+    /// Message shown to the user in the `exit` frame
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TerminatedResponse {
+    pub terminated: usize,
 }
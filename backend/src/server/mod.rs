@@ -0,0 +1,361 @@
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{extract::ConnectInfo, Router};
+use hyper::{body::Incoming, service::service_fn, Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{
+        conn::auto::Builder,
+        graceful::{GracefulShutdown, Watcher},
+    },
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream, UnixListener, UnixStream},
+};
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+
+use crate::config::ServerConfig;
+use shutdown::Shutdown;
+use tls::TlsTerminator;
+
+pub mod shutdown;
+pub mod tls;
+
+/// Time a client gets to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time in-flight HTTP requests get to finish once the listeners are closed
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Address from `server.listen`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// IP address or host name; names bind every address they resolve to
+    Tcp { host: String, port: u16 },
+    /// `unix:/run/deuseda.sock`, for a reverse proxy on the same machine
+    Unix(PathBuf),
+}
+
+impl ListenAddr {
+    /// Parse `host:port`, `[v6]:port` or `unix:<path>`
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(path) = value.strip_prefix("unix:") {
+            return (!path.is_empty()).then(|| Self::Unix(PathBuf::from(path)));
+        }
+
+        let (host, port) = value.rsplit_once(':')?;
+        let host = match host.strip_prefix('[') {
+            Some(v6) => v6.strip_suffix(']')?,
+            // IPv6 addresses need brackets, or the port cannot be told apart
+            None if host.contains([':', ']']) => return None,
+            None => host,
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(Self::Tcp {
+            host: host.to_string(),
+            port: port.parse().ok()?,
+        })
+    }
+}
+
+/// Bound socket the server accepts connections on
+pub enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Bind every configured address. An IPv6 wildcard also accepts IPv4 (dual-stack)
+    /// unless an IPv4 listener on the same port is configured next to it.
+    pub async fn bind_all(config: &ServerConfig) -> Result<Vec<Self>> {
+        let mut tcp = Vec::new();
+        let mut listeners = Vec::new();
+
+        for addr in config.listen_addrs()? {
+            match addr {
+                ListenAddr::Tcp { host, port } => {
+                    let resolved = tokio::net::lookup_host((host.as_str(), port))
+                        .await
+                        .with_context(|| format!("Cannot resolve listen address {}", host))?;
+                    for addr in resolved {
+                        if !tcp.contains(&addr) {
+                            tcp.push(addr);
+                        }
+                    }
+                }
+                ListenAddr::Unix(path) => {
+                    let mode = config.unix_socket_mode()?;
+                    listeners.push(Self::Unix(bind_unix(&path, mode)?));
+                    tracing::info!("Listening on unix:{} (mode {:o})", path.display(), mode);
+                }
+            }
+        }
+
+        listeners.extend(Self::bind_tcp_all(&tcp)?);
+        Ok(listeners)
+    }
+
+    /// Plain HTTP listeners on the addresses of `listeners`, with another port
+    pub fn bind_redirects(listeners: &[Self], port: u16) -> Result<Vec<Self>> {
+        let mut addrs = Vec::new();
+        for listener in listeners {
+            if let Self::Tcp(listener) = listener {
+                let mut addr = listener.local_addr()?;
+                addr.set_port(port);
+                if !addrs.contains(&addr) {
+                    addrs.push(addr);
+                }
+            }
+        }
+        Self::bind_tcp_all(&addrs)
+    }
+
+    /// Port of the first TCP listener, where HTTP is redirected to
+    pub fn tcp_port(listeners: &[Self]) -> Option<u16> {
+        listeners.iter().find_map(|listener| match listener {
+            Self::Tcp(listener) => listener.local_addr().ok().map(|a| a.port()),
+            Self::Unix(_) => None,
+        })
+    }
+
+    fn bind_tcp_all(addrs: &[SocketAddr]) -> Result<Vec<Self>> {
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            let v6_only = addr.is_ipv6()
+                && addrs
+                    .iter()
+                    .any(|other| other.is_ipv4() && other.port() == addr.port());
+            listeners.push(Self::Tcp(bind_tcp(*addr, v6_only)?));
+            tracing::info!("Listening on {}", addr);
+        }
+        Ok(listeners)
+    }
+}
+
+fn bind_tcp(addr: SocketAddr, v6_only: bool) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(v6_only)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("Failed to bind {}", addr))?;
+    socket.listen(1024)?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+fn bind_unix(path: &Path, mode: u32) -> Result<UnixListener> {
+    // A socket left behind by a previous run would make bind fail; never remove anything else
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(anyhow!("{} exists and is not a socket", path.display()));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind unix:{}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+
+    Ok(listener)
+}
+
+/// Serve `app` on every listener until the shutdown starts, then let in-flight
+/// requests finish. With `tls`, TCP listeners speak HTTPS; Unix sockets stay
+/// plain for a local reverse proxy. Upgraded WebSockets are drained by `Shutdown`.
+pub async fn serve(
+    listeners: Vec<Listener>,
+    app: Router,
+    tls: Option<Arc<TlsTerminator>>,
+    shutdown: Arc<Shutdown>,
+) {
+    let graceful = Arc::new(GracefulShutdown::new());
+    let mut tasks = tokio::task::JoinSet::new();
+    for listener in listeners {
+        tasks.spawn(accept_loop(
+            listener,
+            app.clone(),
+            tls.clone(),
+            shutdown.clone(),
+            graceful.clone(),
+        ));
+    }
+    while tasks.join_next().await.is_some() {}
+
+    // Every accept loop has returned its handle by now
+    let Ok(graceful) = Arc::try_unwrap(graceful) else {
+        return;
+    };
+    let open = graceful.count();
+    if tokio::time::timeout(DRAIN_TIMEOUT, graceful.shutdown())
+        .await
+        .is_err()
+    {
+        tracing::warn!("Not all of {} HTTP connections finished in time", open);
+    }
+}
+
+async fn accept_loop(
+    listener: Listener,
+    app: Router,
+    tls: Option<Arc<TlsTerminator>>,
+    shutdown: Arc<Shutdown>,
+    graceful: Arc<GracefulShutdown>,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = accept(&listener) => accepted,
+            _ = shutdown.draining() => break,
+        };
+
+        match accepted {
+            Ok((Stream::Tcp(stream), peer)) => {
+                let _ = stream.set_nodelay(true);
+                let watcher = graceful.watcher();
+                match &tls {
+                    Some(tls) => {
+                        serve_tls_connection(tls.acceptor(), stream, peer, app.clone(), watcher)
+                    }
+                    None => serve_connection(stream, peer, app.clone(), watcher),
+                }
+            }
+            Ok((Stream::Unix(stream), peer)) => {
+                serve_connection(stream, peer, app.clone(), graceful.watcher())
+            }
+            // Running out of file descriptors must not stop the listener for good
+            Err(e) => {
+                tracing::error!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/// Connection accepted on a `Listener`
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+async fn accept(listener: &Listener) -> std::io::Result<(Stream, SocketAddr)> {
+    match listener {
+        Listener::Tcp(listener) => {
+            let (stream, peer) = listener.accept().await?;
+            Ok((Stream::Tcp(stream), peer))
+        }
+        Listener::Unix(listener) => {
+            let (stream, _) = listener.accept().await?;
+            // Peers are local processes (the reverse proxy); with
+            // `server.trust_proxy_headers` the client IP comes from X-Forwarded-For
+            let peer = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+            Ok((Stream::Unix(stream), peer))
+        }
+    }
+}
+
+/// Handshake off the accept loop, then serve the decrypted stream
+fn serve_tls_connection(
+    acceptor: TlsAcceptor,
+    stream: TcpStream,
+    peer: SocketAddr,
+    app: Router,
+    watcher: Watcher,
+) {
+    tokio::spawn(async move {
+        match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => serve_connection(stream, peer, app, watcher),
+            Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", peer, e),
+            Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+        }
+    });
+}
+
+/// HTTP/1.1 (with WebSocket upgrades) or HTTP/2 on one connection
+fn serve_connection<S>(stream: S, peer: SocketAddr, app: Router, watcher: Watcher)
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    // Handlers read the client address through `ConnectInfo`
+    let service = service_fn(move |mut request: Request<Incoming>| {
+        request.extensions_mut().insert(ConnectInfo(peer));
+        app.clone().call(request)
+    });
+
+    tokio::spawn(async move {
+        let builder = Builder::new(TokioExecutor::new());
+        let connection = builder
+            .serve_connection_with_upgrades(TokioIo::new(stream), service)
+            .into_owned();
+        if let Err(e) = watcher.watch(connection).await {
+            tracing::debug!("Connection from {} ended with error: {}", peer, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(host: &str, port: u16) -> Option<ListenAddr> {
+        Some(ListenAddr::Tcp {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    #[test]
+    fn parses_tcp_addresses() {
+        assert_eq!(ListenAddr::parse("127.0.0.1:8080"), tcp("127.0.0.1", 8080));
+        assert_eq!(ListenAddr::parse(" 0.0.0.0:80 "), tcp("0.0.0.0", 80));
+        assert_eq!(
+            ListenAddr::parse("console.internal:443"),
+            tcp("console.internal", 443)
+        );
+        assert_eq!(ListenAddr::parse("[::]:8080"), tcp("::", 8080));
+        assert_eq!(ListenAddr::parse("[::1]:8080"), tcp("::1", 8080));
+    }
+
+    #[test]
+    fn parses_unix_sockets() {
+        assert_eq!(
+            ListenAddr::parse("unix:/run/deuseda/http.sock"),
+            Some(ListenAddr::Unix(PathBuf::from("/run/deuseda/http.sock")))
+        );
+        assert_eq!(ListenAddr::parse("unix:"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for value in [
+            "",
+            "8080",
+            ":8080",
+            "127.0.0.1",
+            "127.0.0.1:http",
+            "127.0.0.1:70000",
+            "[]:8080",
+            "[::1:8080",
+            "::1:8080",
+            "2001:db8::1",
+        ] {
+            assert_eq!(ListenAddr::parse(value), None, "{value:?}");
+        }
+    }
+}
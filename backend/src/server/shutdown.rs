@@ -0,0 +1,116 @@
+use std::{future::Future, time::Duration};
+
+use tokio::sync::watch;
+
+use crate::terminal::registry::{BridgeEvent, BridgeRegistry};
+
+/// Time terminals get to close their SSH channels once the grace period is over
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Stage of a graceful shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Running,
+    /// Listeners are closed and open terminals were asked to reconnect shortly
+    Draining,
+    /// The grace period is over; the remaining terminals close their SSH channels
+    Closing,
+}
+
+/// Coordinates a `SIGTERM`: stop accepting, warn every terminal, give clients
+/// `server.shutdown_grace` to detach from tmux, then close what is left
+pub struct Shutdown {
+    phase: watch::Sender<Phase>,
+    /// One receiver per terminal handler, held until its SSH connection is closed
+    terminals: watch::Sender<()>,
+    grace: Duration,
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            phase: watch::Sender::new(Phase::Running),
+            terminals: watch::Sender::new(()),
+            grace,
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        *self.phase.borrow() != Phase::Running
+    }
+
+    /// Resolves once the shutdown has started
+    pub async fn draining(&self) {
+        let mut phase = self.phase.subscribe();
+        let _ = phase.wait_for(|p| *p != Phase::Running).await;
+    }
+
+    /// Count a terminal handler until the lease is dropped; `None` once the
+    /// shutdown has started, so no new terminals are opened
+    pub fn track(&self) -> Option<TerminalLease> {
+        // Subscribe before checking so a shutdown starting in between still waits for us
+        let lease = TerminalLease {
+            phase: self.phase.subscribe(),
+            _alive: self.terminals.subscribe(),
+        };
+        (!self.is_draining()).then_some(lease)
+    }
+
+    /// Stop the listeners (see `serve`) and drain the terminals
+    pub async fn run(&self, bridges: &BridgeRegistry) {
+        self.phase.send_replace(Phase::Draining);
+
+        let open = bridges.all();
+        tracing::info!(
+            "Shutting down: {} terminals have {}s to reconnect",
+            open.len(),
+            self.grace.as_secs()
+        );
+        for bridge in open {
+            let _ = bridge.events.send(BridgeEvent::ServerRestarting {
+                seconds_left: self.grace.as_secs(),
+                message: "Server is restarting, reconnect shortly".to_string(),
+            });
+        }
+
+        // Stop waiting early once every client has detached on its own
+        if tokio::time::timeout(self.grace, self.terminals.closed())
+            .await
+            .is_ok()
+        {
+            tracing::info!("All terminals detached");
+            return;
+        }
+
+        tracing::info!(
+            "Closing {} remaining terminals",
+            self.terminals.receiver_count()
+        );
+        self.phase.send_replace(Phase::Closing);
+        if tokio::time::timeout(CLOSE_TIMEOUT, self.terminals.closed())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "{} terminals did not close their SSH connection in time",
+                self.terminals.receiver_count()
+            );
+        }
+    }
+}
+
+/// Held by a terminal handler for as long as its SSH connection is open
+pub struct TerminalLease {
+    phase: watch::Receiver<Phase>,
+    _alive: watch::Receiver<()>,
+}
+
+impl TerminalLease {
+    /// Resolves when the grace period is over and the terminal has to close
+    pub fn closing(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut phase = self.phase.clone();
+        async move {
+            let _ = phase.wait_for(|p| *p == Phase::Closing).await;
+        }
+    }
+}
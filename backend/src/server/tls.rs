@@ -0,0 +1,172 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    http::{header, uri::Authority, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Redirect, Response},
+    Router,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        server::WebPkiClientVerifier,
+        RootCertStore,
+    },
+    TlsAcceptor,
+};
+
+use crate::config::TlsConfig;
+
+/// How often the certificate files are checked for renewals
+const WATCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// rustls settings for the HTTPS listeners, rebuilt whenever the
+/// certificate, key or client CA file changes (e.g. a certbot renewal)
+pub struct TlsTerminator {
+    files: TlsConfig,
+    current: RwLock<Arc<rustls::ServerConfig>>,
+    modified: RwLock<Vec<Option<SystemTime>>>,
+}
+
+impl TlsTerminator {
+    /// `None` unless `server.tls.cert_file` and `key_file` are set
+    pub fn load(config: &TlsConfig) -> Result<Option<Arc<Self>>> {
+        if !config.enabled() {
+            return Ok(None);
+        }
+
+        let server_config = build(config)?;
+        tracing::info!(
+            "TLS enabled{}",
+            if config.client_ca_file.is_some() {
+                " with client certificates required"
+            } else {
+                ""
+            }
+        );
+
+        Ok(Some(Arc::new(Self {
+            files: config.clone(),
+            current: RwLock::new(Arc::new(server_config)),
+            modified: RwLock::new(modified(config)),
+        })))
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    /// Poll the files and swap in a new configuration when they change;
+    /// a broken renewal keeps serving the previous certificate
+    pub fn watch(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+            loop {
+                ticker.tick().await;
+
+                let now = modified(&self.files);
+                if *self.modified.read().unwrap() == now {
+                    continue;
+                }
+
+                // Retried on the next tick while it fails, e.g. between writing the cert and the key
+                match build(&self.files) {
+                    Ok(config) => {
+                        *self.current.write().unwrap() = Arc::new(config);
+                        *self.modified.write().unwrap() = now;
+                        tracing::info!("Reloaded TLS certificate");
+                    }
+                    Err(e) => tracing::error!("Failed to reload TLS certificate: {:#}", e),
+                }
+            }
+        });
+    }
+}
+
+fn files(config: &TlsConfig) -> impl Iterator<Item = &PathBuf> {
+    [&config.cert_file, &config.key_file, &config.client_ca_file]
+        .into_iter()
+        .flatten()
+}
+
+fn modified(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    files(config)
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+fn build(config: &TlsConfig) -> Result<rustls::ServerConfig> {
+    let (Some(cert_file), Some(key_file)) = (&config.cert_file, &config.key_file) else {
+        return Err(anyhow!("server.tls needs cert_file and key_file"));
+    };
+
+    let certs = read_certs(cert_file)?;
+    let key = PrivateKeyDer::from_pem_file(key_file)
+        .with_context(|| format!("Failed to read TLS key {}", key_file.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(ca_file)? {
+                roots.add(cert)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("Invalid client CA")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .context("TLS certificate does not match the key")?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(server_config)
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+/// Plain HTTP app that sends every request to the same path over HTTPS on `https_port`
+pub fn redirect_app(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        redirect_to_https(&headers, &uri, https_port)
+    })
+}
+
+fn redirect_to_https(headers: &HeaderMap, uri: &Uri, https_port: u16) -> Response {
+    let Some(authority) = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+    else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let host = authority.host();
+    let host = match https_port {
+        443 => host.to_string(),
+        port => format!("{}:{}", host, port),
+    };
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+
+    Redirect::permanent(&format!("https://{}{}", host, path)).into_response()
+}
@@ -0,0 +1,94 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use rand_core::OsRng;
+use ssh_key::{
+    certificate::{Builder, CertType},
+    Algorithm, LineEnding, PrivateKey,
+};
+
+use super::SshAuth;
+use crate::config::CaConfig;
+
+/// Backdate certificates a little so clock skew between hosts does not reject them
+const CLOCK_SKEW_SECS: u64 = 30;
+
+/// Embedded certificate authority: every SSH connection gets a fresh key pair
+/// and a short-lived user certificate, so the backend never needs the user's password.
+/// SSH servers trust it through `TrustedUserCAKeys`.
+pub struct SshCa {
+    key: PrivateKey,
+    policy: CaConfig,
+}
+
+impl SshCa {
+    /// `None` unless `ssh.ca.key_file` is set
+    pub fn load(config: &CaConfig) -> Result<Option<Self>> {
+        let Some(path) = &config.key_file else {
+            return Ok(None);
+        };
+
+        let key = PrivateKey::read_openssh_file(path)
+            .with_context(|| format!("Failed to read SSH CA key {}", path.display()))?;
+        if key.is_encrypted() {
+            return Err(anyhow!(
+                "SSH CA key {} must not be encrypted",
+                path.display()
+            ));
+        }
+
+        let policy = config.clone();
+        tracing::info!(
+            "SSH certificate authority enabled ({}, certificates valid for {}s)",
+            key.public_key().fingerprint(Default::default()),
+            policy.cert_ttl
+        );
+
+        Ok(Some(Self { key, policy }))
+    }
+
+    /// Mint an ephemeral Ed25519 key pair and a user certificate for `username`
+    pub fn issue(&self, username: &str) -> Result<SshAuth> {
+        let key = PrivateKey::random(&mut OsRng, Algorithm::Ed25519)?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut builder = Builder::new_with_random_nonce(
+            &mut OsRng,
+            key.public_key(),
+            now - CLOCK_SKEW_SECS,
+            now + self.policy.cert_ttl,
+        )?;
+
+        let serial = u64::from_be_bytes(*uuid::Uuid::new_v4().as_bytes().first_chunk().unwrap());
+        builder
+            .serial(serial)?
+            .key_id(format!("deuseda:{}:{:016x}", username, serial))?
+            .cert_type(CertType::User)?
+            .valid_principal(username)?;
+        for principal in &self.policy.extra_principals {
+            builder.valid_principal(principal)?;
+        }
+        if let Some(source_address) = &self.policy.source_address {
+            builder.critical_option("source-address", source_address)?;
+        }
+        if let Some(command) = &self.policy.force_command {
+            builder.critical_option("force-command", command)?;
+        }
+        for extension in &self.policy.extensions {
+            builder.extension(extension, "")?;
+        }
+
+        let certificate = builder.sign(&self.key)?;
+        tracing::info!(
+            "Issued SSH certificate {:016x} for {} (valid {}s)",
+            serial,
+            username,
+            self.policy.cert_ttl
+        );
+
+        Ok(SshAuth::Certificate {
+            private_key: key.to_openssh(LineEnding::LF)?.to_string(),
+            certificate: certificate.to_openssh()?,
+        })
+    }
+}
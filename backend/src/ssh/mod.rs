@@ -0,0 +1,179 @@
+pub mod ca;
+pub mod probe;
+
+use std::{fmt, net::Ipv6Addr, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use ssh2::Session;
+
+use crate::{config::SshConfig, telemetry::scrub::Secret};
+use ca::SshCa;
+
+/// SSH server that terminals and tmux commands connect to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SshTarget {
+    pub host: String,
+    pub port: u16,
+}
+
+impl SshTarget {
+    /// Parse `host`, `host:port`, `[v6]`, `[v6]:port` or a bare IPv6 address
+    /// (port defaults to 22)
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let (host, port) = if let Some(rest) = value.strip_prefix('[') {
+            let (v6, port) = rest.split_once(']')?;
+            let port = match port {
+                "" => None,
+                port => Some(port.strip_prefix(':')?),
+            };
+            (v6, port)
+        } else if value.parse::<Ipv6Addr>().is_ok() {
+            (value, None)
+        } else {
+            match value.split_once(':') {
+                // Anything else with more than one colon is ambiguous
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                Some(_) => return None,
+                None => (value, None),
+            }
+        };
+        if host.is_empty() || host.contains(['[', ']']) {
+            return None;
+        }
+
+        Some(Self {
+            host: host.to_string(),
+            port: port.map_or(Some(22), |p| p.parse().ok())?,
+        })
+    }
+}
+
+impl fmt::Display for SshTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+/// How the backend authenticates to the SSH server on the user's behalf
+#[derive(Clone)]
+pub enum SshAuth {
+    /// Linux password entered at login
+    Password(Secret),
+    /// Private key kept on the server, used for SSO logins
+    KeyFile(PathBuf),
+    /// Ephemeral key with a short-lived certificate from the embedded CA
+    Certificate {
+        private_key: String,
+        certificate: String,
+    },
+}
+
+impl SshAuth {
+    /// Per-user private key `ssh.user_key_dir/<username>`
+    pub fn user_key(config: &SshConfig, username: &str) -> Option<Self> {
+        let dir = config.user_key_dir.as_ref()?;
+        if username.is_empty() || username.contains(['/', '\\']) || username.starts_with('.') {
+            return None;
+        }
+
+        let path = dir.join(username);
+        path.is_file().then_some(Self::KeyFile(path))
+    }
+
+    /// Credentials the server supplies itself, so the browser does not send a password:
+    /// a freshly minted certificate when the CA is enabled, otherwise the SSO user's key.
+    /// `None` for password logins without a CA.
+    pub fn from_server(
+        config: &SshConfig,
+        ca: Option<&SshCa>,
+        username: &str,
+        sso: bool,
+    ) -> Result<Option<Self>> {
+        if let Some(ca) = ca {
+            return ca.issue(username).map(Some);
+        }
+        if sso {
+            return Self::user_key(config, username)
+                .map(Some)
+                .ok_or_else(|| anyhow!("No SSH key configured for {}", username));
+        }
+        Ok(None)
+    }
+
+    pub fn authenticate(&self, session: &Session, username: &str) -> Result<(), ssh2::Error> {
+        match self {
+            Self::Password(password) => {
+                session.userauth_password(username, password.expose())?;
+                // Only a password the server accepted is worth masking in the logs
+                password.mask();
+                Ok(())
+            }
+            Self::KeyFile(path) => session.userauth_pubkey_file(username, None, path, None),
+            Self::Certificate {
+                private_key,
+                certificate,
+            } => session.userauth_pubkey_memory(username, Some(certificate), private_key, None),
+        }
+    }
+}
+
+impl fmt::Debug for SshAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Password(_) => f.write_str("Password(..)"),
+            Self::KeyFile(path) => write!(f, "KeyFile({})", path.display()),
+            Self::Certificate { .. } => f.write_str("Certificate(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(host: &str, port: u16) -> Option<SshTarget> {
+        Some(SshTarget {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    #[test]
+    fn parses_names_and_ipv4() {
+        assert_eq!(SshTarget::parse("web-1"), target("web-1", 22));
+        assert_eq!(SshTarget::parse(" web-1:2222 "), target("web-1", 2222));
+        assert_eq!(SshTarget::parse("10.0.0.5:22"), target("10.0.0.5", 22));
+        assert_eq!(SshTarget::parse(""), None);
+        assert_eq!(SshTarget::parse(":22"), None);
+        assert_eq!(SshTarget::parse("web-1:ssh"), None);
+    }
+
+    #[test]
+    fn parses_ipv6() {
+        assert_eq!(SshTarget::parse("2001:db8::1"), target("2001:db8::1", 22));
+        assert_eq!(SshTarget::parse("::1"), target("::1", 22));
+        assert_eq!(SshTarget::parse("[2001:db8::1]"), target("2001:db8::1", 22));
+        assert_eq!(
+            SshTarget::parse("[2001:db8::1]:2222"),
+            target("2001:db8::1", 2222)
+        );
+        // Not an address, so the port cannot be told apart
+        assert_eq!(SshTarget::parse("2001:db8::zz:22"), None);
+        assert_eq!(SshTarget::parse("[2001:db8::1]2222"), None);
+        assert_eq!(SshTarget::parse("[2001:db8::1"), None);
+        assert_eq!(SshTarget::parse("[]:22"), None);
+    }
+
+    #[test]
+    fn formats_ipv6_with_brackets() {
+        let v6 = SshTarget::parse("[2001:db8::1]:2222").unwrap();
+        assert_eq!(v6.to_string(), "[2001:db8::1]:2222");
+        assert_eq!(SshTarget::parse(&v6.to_string()), Some(v6));
+        assert_eq!(SshTarget::parse("web-1").unwrap().to_string(), "web-1:22");
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::TcpStream,
+};
+
+use super::SshTarget;
+use crate::config::HealthConfig;
+
+/// Bytes read while looking for the banner; servers may send other lines first (RFC 4253 4.2)
+const MAX_PREAMBLE: u64 = 8 * 1024;
+
+/// Outcome of connecting to an SSH server and reading its banner
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub up: bool,
+    /// Connect plus banner, or until the probe failed
+    pub latency_ms: u64,
+    /// Identification string such as `SSH-2.0-OpenSSH_9.6`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// Reachability of the SSH servers, probed at most once per `health.cache_ttl`
+#[derive(Debug, Default)]
+pub struct SshProbe {
+    cache: Mutex<HashMap<SshTarget, (Instant, ProbeResult)>>,
+}
+
+impl SshProbe {
+    /// Results in the order of `targets`; stale ones are probed concurrently
+    pub async fn check(&self, targets: &[SshTarget], config: &HealthConfig) -> Vec<ProbeResult> {
+        let ttl = Duration::from_secs(config.cache_ttl);
+        let timeout = Duration::from_secs(config.probe_timeout);
+
+        // Forget hosts that were removed from the configuration
+        self.cache
+            .lock()
+            .unwrap()
+            .retain(|target, _| targets.contains(target));
+
+        let probes = targets.iter().map(|target| async move {
+            if let Some(result) = self.cached(target, ttl) {
+                return result;
+            }
+            let result = probe(target, timeout).await;
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(target.clone(), (Instant::now(), result.clone()));
+            result
+        });
+        futures_util::future::join_all(probes).await
+    }
+
+    fn cached(&self, target: &SshTarget, ttl: Duration) -> Option<ProbeResult> {
+        let cache = self.cache.lock().unwrap();
+        let (checked, result) = cache.get(target)?;
+        (checked.elapsed() < ttl).then(|| result.clone())
+    }
+}
+
+async fn probe(target: &SshTarget, timeout: Duration) -> ProbeResult {
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(timeout, read_banner(target))
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("No SSH banner within {}s", timeout.as_secs())));
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    if let Err(e) = &outcome {
+        tracing::warn!("SSH server {} is not reachable: {:#}", target, e);
+    }
+    let (banner, error) = match outcome {
+        Ok(banner) => (Some(banner), None),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+    ProbeResult {
+        up: banner.is_some(),
+        latency_ms,
+        banner,
+        error,
+        checked_at: Utc::now(),
+    }
+}
+
+/// Connect and read lines until the `SSH-` identification string
+async fn read_banner(target: &SshTarget) -> Result<String> {
+    let stream = TcpStream::connect((target.host.as_str(), target.port))
+        .await
+        .context("Connection failed")?;
+    let mut reader = BufReader::new(stream.take(MAX_PREAMBLE));
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow!("Connection closed without an SSH banner"));
+        }
+        if line.starts_with("SSH-") {
+            return Ok(line.trim_end().to_string());
+        }
+    }
+}
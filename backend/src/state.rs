@@ -0,0 +1,66 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+
+use crate::{
+    auth::{
+        oidc::OidcProvider, rbac::RbacPolicy, throttle::LoginThrottle, tickets::TicketStore,
+        tokens::TokenStore,
+    },
+    config::{Config, LiveConfig},
+    middleware::origin::AllowedOrigins,
+    server::shutdown::Shutdown,
+    ssh::{ca::SshCa, probe::SshProbe},
+    terminal::{
+        broadcast::BroadcastGroups, limits::SessionLimits, registry::BridgeRegistry,
+        share::ShareLinks,
+    },
+};
+
+/// Shared application state handed to every handler
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<LiveConfig>,
+    pub bridges: Arc<BridgeRegistry>,
+    pub broadcasts: Arc<BroadcastGroups>,
+    pub shares: Arc<ShareLinks>,
+    pub limits: Arc<SessionLimits>,
+    pub tokens: Arc<TokenStore>,
+    pub login_throttle: Arc<LoginThrottle>,
+    pub rbac: Arc<RbacPolicy>,
+    pub tickets: Arc<TicketStore>,
+    pub origins: Arc<AllowedOrigins>,
+    pub shutdown: Arc<Shutdown>,
+    /// Cached SSH reachability for `/health/ready`
+    pub ssh_probe: Arc<SshProbe>,
+    /// `None` when SSO is not configured
+    pub oidc: Option<Arc<OidcProvider>>,
+    /// `None` when SSH connections use the user's password or key
+    pub ssh_ca: Option<Arc<SshCa>>,
+}
+
+impl AppState {
+    /// Build the services that depend on settings only read at startup
+    pub fn new(config: Config) -> Result<Self> {
+        let auth = &config.auth;
+        let state = Self {
+            bridges: Arc::new(BridgeRegistry::default()),
+            broadcasts: Arc::new(BroadcastGroups::default()),
+            shares: Arc::new(ShareLinks::default()),
+            limits: Arc::new(SessionLimits::default()),
+            tokens: Arc::new(TokenStore::new(auth)?),
+            login_throttle: Arc::new(LoginThrottle::new(&auth.throttle)),
+            rbac: Arc::new(RbacPolicy::load(auth.rbac_policy_file.as_deref())?),
+            tickets: Arc::new(TicketStore::default()),
+            origins: Arc::new(AllowedOrigins::new(&config.server.cors_allowed_origins)),
+            shutdown: Arc::new(Shutdown::new(Duration::from_secs(
+                config.server.shutdown_grace,
+            ))),
+            ssh_probe: Arc::new(SshProbe::default()),
+            oidc: OidcProvider::new(&auth.oidc)?.map(Arc::new),
+            ssh_ca: SshCa::load(&config.ssh.ca)?.map(Arc::new),
+            config: Arc::new(LiveConfig::new(config)),
+        };
+        Ok(state)
+    }
+}
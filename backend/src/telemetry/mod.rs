@@ -0,0 +1,170 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+};
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider as _};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use serde::Deserialize;
+use tokio::task::JoinHandle;
+use tracing::{field, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{fmt, layer::SubscriberExt, EnvFilter};
+
+use crate::{config::TelemetryConfig, middleware::request_id::RequestId};
+use scrub::{Scrubbed, ScrubbedExporter};
+
+pub mod scrub;
+
+/// How log lines are written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human-readable line per event
+    #[default]
+    Text,
+    /// One JSON object per event with the fields of every enclosing span, for log pipelines
+    Json,
+}
+
+impl LogFormat {
+    /// `LOG_FORMAT` alone, for the lines logged before the configuration is loaded
+    pub fn from_env() -> Self {
+        std::env::var("LOG_FORMAT")
+            .ok()
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("expected `text` or `json`".to_string()),
+        }
+    }
+}
+
+/// Console logging filtered by `RUST_LOG`, plus span export when `provider` is set.
+/// Every line is scrubbed of credentials before it is written.
+pub fn subscriber(
+    provider: Option<&SdkTracerProvider>,
+    format: LogFormat,
+) -> impl Subscriber + Send + Sync {
+    // `RUST_LOG` decides which spans are exported as well
+    let otel = provider.map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer("deuseda")));
+
+    let (text, json) = match format {
+        LogFormat::Text => (
+            Some(fmt::layer().with_writer(Scrubbed(std::io::stdout))),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(false)
+                    .with_span_list(true)
+                    .with_writer(Scrubbed(std::io::stdout)),
+            ),
+        ),
+    };
+
+    tracing_subscriber::registry()
+        .with(otel)
+        .with(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "deuseda=debug,tower_http=debug".into()),
+        )
+        .with(text)
+        .with(json)
+}
+
+/// Exporter for `telemetry.otlp_endpoint` (OTLP/HTTP), or `None` when export is off.
+/// The provider batches spans and has to be shut down to flush them. Spans are scrubbed
+/// like log lines before they leave the process.
+pub fn tracer_provider(config: &TelemetryConfig) -> Result<Option<SdkTracerProvider>> {
+    let Some(endpoint) = &config.otlp_endpoint else {
+        return Ok(None);
+    };
+
+    // An endpoint set in code is used as is, unlike OTEL_EXPORTER_OTLP_ENDPOINT
+    let traces = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&traces)
+        .build()
+        .context("Failed to create the OTLP exporter")?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(ScrubbedExporter(exporter))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    // Continue traces started by a proxy or the browser (W3C `traceparent`)
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(Some(provider))
+}
+
+/// Span for one HTTP request, child of the caller's trace when it sent a `traceparent`.
+/// WebSocket connections are traced under the span of their upgrade request.
+pub fn http_span(request: &Request) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str());
+    let request_id = request.extensions().get::<RequestId>();
+
+    // The path only: query strings carry terminal tickets
+    let span = tracing::info_span!(
+        "request",
+        otel.name = format!("{} {}", request.method(), route),
+        request_id = request_id.map(field::display),
+        method = %request.method(),
+        path = request.uri().path(),
+        version = ?request.version(),
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    // Only fails while export is off
+    let _ = span.set_parent(parent);
+    span
+}
+
+/// `spawn_blocking` that stays in the caller's span, so SSH work done on the
+/// blocking pool shows up in the same trace
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(f))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use super::registry::{BridgeHandle, BridgeRegistry};
+use crate::ssh::SshTarget;
+
+/// How long a group created over REST may wait for its `/ws/broadcast` socket
+pub const UNATTACHED_TTL: Duration = Duration::from_secs(120);
+
+/// tmux session that receives broadcast input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastTarget {
+    pub target: SshTarget,
+    pub session_id: String,
+}
+
+/// Set of terminal sessions that receive the same keystrokes (cluster-ssh style).
+/// Output is not merged: every target keeps streaming on its own `/ws/terminal` socket.
+/// A group lives until its owner stops it, an admin stops it or its socket closes;
+/// one whose socket never connects is dropped after `UNATTACHED_TTL`.
+#[derive(Debug, Clone)]
+pub struct BroadcastGroup {
+    pub id: Uuid,
+    pub owner: String,
+    pub targets: Vec<BroadcastTarget>,
+    stop: watch::Sender<bool>,
+}
+
+impl BroadcastGroup {
+    /// Resolves once the group has been stopped
+    pub fn subscribe_stop(&self) -> watch::Receiver<bool> {
+        self.stop.subscribe()
+    }
+
+    /// Live bridge for each target, `None` when the session has no open terminal
+    pub fn resolve_bridges(&self, bridges: &BridgeRegistry) -> Vec<Option<BridgeHandle>> {
+        self.targets
+            .iter()
+            .map(|t| bridges.find_session(&self.owner, &t.target, &t.session_id))
+            .collect()
+    }
+
+    /// Live bridges that take broadcast input. Sessions whose keyboard the owner handed
+    /// to a collaborator are skipped, like the owner's own socket would be.
+    pub fn input_bridges(&self, bridges: &BridgeRegistry) -> Vec<BridgeHandle> {
+        self.resolve_bridges(bridges)
+            .into_iter()
+            .flatten()
+            .filter(|bridge| {
+                // The owner's participant id is the bridge id
+                let holds = bridge.collab.holds_keyboard(bridge.id);
+                if !holds {
+                    tracing::debug!(
+                        "Skipped broadcast input for session {}: the owner does not hold the keyboard",
+                        bridge.session_id
+                    );
+                }
+                holds
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    group: BroadcastGroup,
+    created_at: Instant,
+    attached: bool,
+}
+
+impl Entry {
+    fn expired(&self, now: Instant) -> bool {
+        !self.attached && now.duration_since(self.created_at) >= UNATTACHED_TTL
+    }
+}
+
+/// Broadcast groups created by users. Broadcasting can be turned off entirely
+/// with `terminal.broadcast_enabled = false`, which also stops running groups on reload.
+#[derive(Debug, Default)]
+pub struct BroadcastGroups {
+    groups: RwLock<HashMap<Uuid, Entry>>,
+}
+
+impl BroadcastGroups {
+    /// Create a group, or `None` when `owner` already has `limit` groups
+    pub fn create(
+        &self,
+        owner: &str,
+        targets: Vec<BroadcastTarget>,
+        limit: usize,
+    ) -> Option<BroadcastGroup> {
+        let mut groups = self.groups.write().unwrap();
+        let now = Instant::now();
+        groups.retain(|_, entry| !entry.expired(now));
+        if groups.values().filter(|e| e.group.owner == owner).count() >= limit {
+            return None;
+        }
+
+        let (stop, _) = watch::channel(false);
+        let group = BroadcastGroup {
+            id: Uuid::new_v4(),
+            owner: owner.to_string(),
+            targets,
+            stop,
+        };
+        let entry = Entry {
+            group: group.clone(),
+            created_at: now,
+            attached: false,
+        };
+        groups.insert(group.id, entry);
+        Some(group)
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<BroadcastGroup> {
+        let now = Instant::now();
+        let groups = self.groups.read().unwrap();
+        groups
+            .get(id)
+            .filter(|e| !e.expired(now))
+            .map(|e| e.group.clone())
+    }
+
+    /// Mark a group as driven by a socket, so it no longer expires
+    pub fn attach(&self, id: &Uuid) -> Option<BroadcastGroup> {
+        let now = Instant::now();
+        let mut groups = self.groups.write().unwrap();
+        let entry = groups.get_mut(id).filter(|e| !e.expired(now))?;
+        entry.attached = true;
+        Some(entry.group.clone())
+    }
+
+    /// Every group, for administrators
+    pub fn all(&self) -> Vec<BroadcastGroup> {
+        self.list(|_| true)
+    }
+
+    pub fn list_for(&self, owner: &str) -> Vec<BroadcastGroup> {
+        self.list(|g| g.owner == owner)
+    }
+
+    fn list(&self, filter: impl Fn(&BroadcastGroup) -> bool) -> Vec<BroadcastGroup> {
+        let now = Instant::now();
+        self.groups
+            .read()
+            .unwrap()
+            .values()
+            .filter(|e| !e.expired(now) && filter(&e.group))
+            .map(|e| e.group.clone())
+            .collect()
+    }
+
+    /// Stop a group and disconnect its broadcast sockets
+    pub fn stop(&self, id: &Uuid) -> Option<BroadcastGroup> {
+        let entry = self.groups.write().unwrap().remove(id)?;
+        let _ = entry.group.stop.send(true);
+        Some(entry.group)
+    }
+
+    /// Stop every group
+    pub fn stop_all(&self) {
+        let groups: Vec<_> = self.groups.write().unwrap().drain().collect();
+        for (_, entry) in &groups {
+            let _ = entry.group.stop.send(true);
+        }
+        if !groups.is_empty() {
+            tracing::info!("Stopped {} broadcast groups", groups.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::terminal::registry::ClientInfo;
+
+    fn target(session_id: &str) -> BroadcastTarget {
+        BroadcastTarget {
+            target: SshTarget {
+                host: "web-1".to_string(),
+                port: 22,
+            },
+            session_id: session_id.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn create_and_stop() {
+        let groups = BroadcastGroups::default();
+        let group = groups.create("alice", vec![target("a")], 5).unwrap();
+        let mut stopped = group.subscribe_stop();
+
+        assert_eq!(groups.list_for("alice").len(), 1);
+        assert!(groups.list_for("bob").is_empty());
+        assert!(groups.get(&group.id).is_some());
+
+        assert!(groups.stop(&group.id).is_some());
+        assert!(*stopped.borrow_and_update());
+        assert!(groups.get(&group.id).is_none());
+        assert!(groups.stop(&group.id).is_none());
+    }
+
+    #[tokio::test]
+    async fn stop_all_stops_every_group() {
+        let groups = BroadcastGroups::default();
+        let first = groups.create("alice", vec![target("a")], 5).unwrap();
+        let second = groups.create("bob", vec![target("b")], 5).unwrap();
+        let (mut first_stop, mut second_stop) = (first.subscribe_stop(), second.subscribe_stop());
+
+        groups.stop_all();
+        assert!(groups.all().is_empty());
+        assert!(*first_stop.borrow_and_update());
+        assert!(*second_stop.borrow_and_update());
+    }
+
+    #[tokio::test]
+    async fn groups_per_owner_are_limited() {
+        let groups = BroadcastGroups::default();
+        assert!(groups.create("alice", vec![target("a")], 2).is_some());
+        let second = groups.create("alice", vec![target("b")], 2).unwrap();
+        assert!(groups.create("alice", vec![target("c")], 2).is_none());
+        assert!(groups.create("bob", vec![target("a")], 2).is_some());
+
+        groups.stop(&second.id);
+        assert!(groups.create("alice", vec![target("c")], 2).is_some());
+    }
+
+    #[tokio::test]
+    async fn unattached_groups_expire() {
+        let groups = BroadcastGroups::default();
+        let idle = groups.create("alice", vec![target("a")], 2).unwrap();
+        let driven = groups.create("alice", vec![target("b")], 2).unwrap();
+        assert!(groups.attach(&driven.id).is_some());
+
+        for entry in groups.groups.write().unwrap().values_mut() {
+            entry.created_at -= UNATTACHED_TTL;
+        }
+        assert!(groups.get(&idle.id).is_none());
+        assert!(groups.attach(&idle.id).is_none());
+        assert!(groups.get(&driven.id).is_some());
+
+        // The expired group no longer counts against the limit
+        assert!(groups.create("alice", vec![target("c")], 2).is_some());
+    }
+
+    #[tokio::test]
+    async fn sessions_handed_to_a_collaborator_get_no_input() {
+        let bridges = Arc::new(BridgeRegistry::default());
+        let client = ClientInfo {
+            ip: [127, 0, 0, 1].into(),
+            user_agent: None,
+        };
+        let (kept, _kept_input) =
+            BridgeHandle::new("alice", &target("a").target, "a", client.clone());
+        let (handed, _handed_input) = BridgeHandle::new("alice", &target("b").target, "b", client);
+        let _guards = (
+            bridges.register(kept.clone()),
+            bridges.register(handed.clone()),
+        );
+
+        let collaborator = Uuid::new_v4();
+        handed.collab.join(collaborator, "bob", true);
+        handed.collab.grant(handed.id, "bob").unwrap();
+
+        let groups = BroadcastGroups::default();
+        let group = groups
+            .create("alice", vec![target("a"), target("b"), target("gone")], 5)
+            .unwrap();
+        let ids: Vec<Uuid> = group.input_bridges(&bridges).iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![kept.id]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use super::registry::BridgeEvent;
+
+/// Someone attached to a bridge: the owner, a collaborator or a read-only viewer
+#[derive(Debug, Clone)]
+struct Participant {
+    id: Uuid,
+    username: String,
+    can_type: bool,
+}
+
+/// Entry of the `presence` frame sent to every participant
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceEntry {
+    pub username: String,
+    pub owner: bool,
+    pub can_type: bool,
+    pub has_keyboard: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ControlError {
+    #[error("only the keyboard holder or the owner can hand over control")]
+    NotHolder,
+    #[error("only the owner can revoke control")]
+    NotOwner,
+    #[error("no participant named {0} may take the keyboard")]
+    UnknownParticipant(String),
+}
+
+#[derive(Debug)]
+struct Keyboard {
+    participants: Vec<Participant>,
+    /// Participant whose input reaches the terminal
+    holder: Uuid,
+}
+
+/// Server-enforced "who has the keyboard" token for a shared terminal bridge.
+/// The owner's connection holds it until they grant it to a collaborator.
+#[derive(Debug)]
+pub struct Collaboration {
+    owner_id: Uuid,
+    keyboard: Mutex<Keyboard>,
+    events: broadcast::Sender<BridgeEvent>,
+}
+
+impl Collaboration {
+    pub fn new(owner_id: Uuid, owner: &str, events: broadcast::Sender<BridgeEvent>) -> Self {
+        Self {
+            owner_id,
+            keyboard: Mutex::new(Keyboard {
+                participants: vec![Participant {
+                    id: owner_id,
+                    username: owner.to_string(),
+                    can_type: true,
+                }],
+                holder: owner_id,
+            }),
+            events,
+        }
+    }
+
+    pub fn join(&self, id: Uuid, username: &str, can_type: bool) {
+        self.keyboard
+            .lock()
+            .unwrap()
+            .participants
+            .push(Participant {
+                id,
+                username: username.to_string(),
+                can_type,
+            });
+        self.publish_presence();
+    }
+
+    /// Remove a participant; the keyboard falls back to the owner if they held it
+    pub fn leave(&self, id: Uuid) {
+        {
+            let mut keyboard = self.keyboard.lock().unwrap();
+            keyboard.participants.retain(|p| p.id != id);
+            if keyboard.holder == id {
+                keyboard.holder = self.owner_id;
+            }
+        }
+        self.publish_presence();
+    }
+
+    pub fn holds_keyboard(&self, id: Uuid) -> bool {
+        self.keyboard.lock().unwrap().holder == id
+    }
+
+    /// Ask the current holder for the keyboard
+    pub fn request(&self, id: Uuid) {
+        let from = self
+            .keyboard
+            .lock()
+            .unwrap()
+            .participants
+            .iter()
+            .find(|p| p.id == id && p.can_type)
+            .map(|p| p.username.clone());
+
+        if let Some(from) = from {
+            let _ = self.events.send(BridgeEvent::ControlRequested { from });
+        }
+    }
+
+    /// Hand the keyboard to another participant (holder or owner only)
+    pub fn grant(&self, by: Uuid, to: &str) -> Result<(), ControlError> {
+        {
+            let mut keyboard = self.keyboard.lock().unwrap();
+            if keyboard.holder != by && by != self.owner_id {
+                return Err(ControlError::NotHolder);
+            }
+
+            let next = keyboard
+                .participants
+                .iter()
+                .find(|p| p.username == to && p.can_type)
+                .map(|p| p.id)
+                .ok_or_else(|| ControlError::UnknownParticipant(to.to_string()))?;
+            keyboard.holder = next;
+        }
+        self.publish_presence();
+        Ok(())
+    }
+
+    /// Take the keyboard back (owner only)
+    pub fn revoke(&self, by: Uuid) -> Result<(), ControlError> {
+        if by != self.owner_id {
+            return Err(ControlError::NotOwner);
+        }
+
+        self.keyboard.lock().unwrap().holder = self.owner_id;
+        self.publish_presence();
+        Ok(())
+    }
+
+    fn publish_presence(&self) {
+        let participants = {
+            let keyboard = self.keyboard.lock().unwrap();
+            keyboard
+                .participants
+                .iter()
+                .map(|p| PresenceEntry {
+                    username: p.username.clone(),
+                    owner: p.id == self.owner_id,
+                    can_type: p.can_type,
+                    has_keyboard: p.id == keyboard.holder,
+                })
+                .collect()
+        };
+
+        let _ = self.events.send(BridgeEvent::Presence { participants });
+    }
+}
@@ -0,0 +1,552 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::TcpStream,
+    sync::Arc,
+};
+
+use ssh2::{Channel, Session};
+use tokio::{
+    sync::mpsc::{self, error::TryRecvError},
+    task::JoinHandle,
+};
+use tracing::Span;
+
+use super::{
+    exit::ExitReason,
+    idle::{Activity, ExpiryKind, IdleAction, IdleCheck, IdlePolicy},
+    is_valid_session_name, kill_tmux_session_via_ssh,
+    registry::BridgeStats,
+};
+use crate::{
+    metrics::metrics,
+    ssh::{SshAuth, SshTarget},
+    telemetry,
+};
+
+/// Request for an SSH connection worker
+pub enum ChannelCommand {
+    /// Open a PTY channel attached to a tmux session
+    Open {
+        channel: u32,
+        session_id: String,
+        cols: u32,
+        rows: u32,
+        /// Keystrokes for the channel; dropped when the channel closes
+        input: mpsc::Receiver<Vec<u8>>,
+        /// Traffic counters of the bridge the channel belongs to
+        stats: Arc<BridgeStats>,
+        /// Bridge span the channel's SSH work is traced under
+        span: Span,
+    },
+    Resize {
+        channel: u32,
+        cols: u32,
+        rows: u32,
+    },
+    /// Close the channel, reporting `reason` (`ClientClosed` when the client asked)
+    Close {
+        channel: u32,
+        reason: ExitReason,
+    },
+}
+
+/// Notification from an SSH connection worker
+#[derive(Debug)]
+pub enum ChannelEvent {
+    Opened {
+        channel: u32,
+    },
+    Output {
+        channel: u32,
+        data: Vec<u8>,
+    },
+    /// The channel will be closed for inactivity or age unless it becomes active
+    IdleWarning {
+        channel: u32,
+        seconds_left: u64,
+        kind: ExpiryKind,
+    },
+    Closed {
+        channel: u32,
+        reason: ExitReason,
+    },
+}
+
+/// One authenticated SSH connection serving any number of tmux channels.
+/// All channel I/O runs on a single blocking thread because libssh2 sessions
+/// are not meant to be driven concurrently.
+pub struct SshConnection {
+    pub commands: mpsc::Sender<ChannelCommand>,
+    pub task: JoinHandle<Result<(), ExitReason>>,
+}
+
+impl SshConnection {
+    /// Connect and authenticate in the background; channels can be opened right away.
+    /// The worker stops once every `commands` sender has been dropped.
+    pub fn spawn(
+        target: SshTarget,
+        username: String,
+        auth: SshAuth,
+        idle: IdlePolicy,
+        events: mpsc::Sender<ChannelEvent>,
+    ) -> Self {
+        let (commands, rx_commands) = mpsc::channel::<ChannelCommand>(100);
+
+        let task = telemetry::spawn_blocking(move || {
+            let mut worker = Worker {
+                commands: rx_commands,
+                events,
+                idle,
+                channels: HashMap::new(),
+            };
+
+            let session = match connect(&target, &username, &auth) {
+                Ok(session) => session,
+                Err(e) => {
+                    worker.fail_pending(&e);
+                    return Err(e);
+                }
+            };
+
+            worker.run(&session);
+            Ok(())
+        });
+
+        Self { commands, task }
+    }
+}
+
+/// Connect to the SSH server and authenticate as the user
+#[tracing::instrument(name = "ssh_connect", skip_all, fields(user = username, host = %target))]
+pub fn connect(target: &SshTarget, username: &str, auth: &SshAuth) -> Result<Session, ExitReason> {
+    // Connect to SSH server
+    let host = target.to_string();
+    let tcp = match tracing::info_span!("tcp_connect").in_scope(|| {
+        metrics().ssh_connect(&host, || {
+            TcpStream::connect((target.host.as_str(), target.port))
+        })
+    }) {
+        Ok(tcp) => tcp,
+        Err(e) => {
+            tracing::error!("Failed to connect to SSH server: {}", e);
+            return Err(ExitReason::HostUnreachable {
+                message: format!("Failed to connect to SSH server: {}", e),
+            });
+        }
+    };
+
+    // Create SSH session
+    let mut session = match Session::new() {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::error!("Failed to create SSH session: {}", e);
+            return Err(ExitReason::ssh_error(format!(
+                "Failed to create SSH session: {}",
+                e
+            )));
+        }
+    };
+
+    session.set_tcp_stream(tcp);
+    let handshake = tracing::info_span!("ssh_handshake")
+        .in_scope(|| metrics().ssh_handshake(&host, || session.handshake()));
+    if let Err(e) = handshake {
+        tracing::error!("SSH handshake failed: {}", e);
+        return Err(ExitReason::ssh_error(format!(
+            "SSH handshake failed: {}",
+            e
+        )));
+    }
+
+    // Authenticate
+    let authenticated =
+        tracing::info_span!("ssh_auth").in_scope(|| auth.authenticate(&session, username));
+    if let Err(e) = authenticated {
+        tracing::error!("SSH authentication failed: {}", e);
+        return Err(ExitReason::AuthFailed {
+            message: format!("SSH authentication failed: {}", e),
+        });
+    }
+
+    if !session.authenticated() {
+        tracing::error!("SSH authentication failed");
+        return Err(ExitReason::AuthFailed {
+            message: "SSH authentication failed".to_string(),
+        });
+    }
+
+    Ok(session)
+}
+
+/// Request PTY and shell with tmux for session persistence
+#[tracing::instrument(name = "tmux_attach", skip(session, cols, rows))]
+fn open_channel(
+    session: &Session,
+    session_id: &str,
+    cols: u32,
+    rows: u32,
+) -> Result<Channel, ExitReason> {
+    // The name goes into a shell command; handlers reject bad names before they get here
+    if !is_valid_session_name(session_id) {
+        tracing::error!("Invalid tmux session name {:?}", session_id);
+        return Err(ExitReason::ssh_error(format!(
+            "Invalid tmux session name {:?}",
+            session_id
+        )));
+    }
+
+    let mut channel = match session.channel_session() {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!("Failed to open channel: {}", e);
+            return Err(ExitReason::ssh_error(format!(
+                "Failed to open channel: {}",
+                e
+            )));
+        }
+    };
+
+    let pty_modes = ssh2::PtyModes::new();
+    if let Err(e) = channel.request_pty("xterm-256color", Some(pty_modes), Some((cols, rows, 0, 0)))
+    {
+        tracing::error!("Failed to request PTY: {}", e);
+        return Err(ExitReason::ssh_error(format!(
+            "Failed to request PTY: {}",
+            e
+        )));
+    }
+
+    // Try to attach to existing session, or create new one if it doesn't exist
+    let tmux_command = format!(
+        "tmux attach-session -t '{}' || tmux new-session -s '{}'",
+        session_id, session_id
+    );
+
+    if let Err(e) = channel.exec(&tmux_command) {
+        tracing::error!("Failed to execute tmux command: {}", e);
+        return Err(ExitReason::ssh_error(format!(
+            "Failed to execute tmux command: {}",
+            e
+        )));
+    }
+
+    Ok(channel)
+}
+
+struct OpenChannel {
+    channel: Channel,
+    input: mpsc::Receiver<Vec<u8>>,
+    session_id: String,
+    activity: Activity,
+    stats: Arc<BridgeStats>,
+    span: Span,
+}
+
+struct Worker {
+    commands: mpsc::Receiver<ChannelCommand>,
+    events: mpsc::Sender<ChannelEvent>,
+    idle: IdlePolicy,
+    channels: HashMap<u32, OpenChannel>,
+}
+
+impl Worker {
+    /// Report every queued `Open` as failed after the connection could not be established
+    fn fail_pending(&mut self, reason: &ExitReason) {
+        self.commands.close();
+        while let Ok(command) = self.commands.try_recv() {
+            if let ChannelCommand::Open { channel, .. } = command {
+                self.emit(ChannelEvent::Closed {
+                    channel,
+                    reason: reason.clone(),
+                });
+            }
+        }
+    }
+
+    fn emit(&self, event: ChannelEvent) {
+        let _ = self.events.blocking_send(event);
+    }
+
+    fn run(&mut self, session: &Session) {
+        // Now set to non-blocking mode for I/O
+        session.set_blocking(false);
+
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            // Apply pending commands
+            loop {
+                match self.commands.try_recv() {
+                    Ok(command) => self.handle_command(session, command),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        tracing::info!("WebSocket disconnected");
+                        self.close_all();
+                        return;
+                    }
+                }
+            }
+
+            // Channels to close; `None` means the remote side hit EOF
+            let mut finished = Vec::new();
+            for (&id, open) in self.channels.iter_mut() {
+                match pump_channel(open, &mut buffer) {
+                    Ok(pumped) => {
+                        if pumped.active {
+                            open.activity.touch();
+                        }
+                        let delivered = pumped.chunks.into_iter().all(|data| {
+                            self.events
+                                .blocking_send(ChannelEvent::Output { channel: id, data })
+                                .is_ok()
+                        });
+                        if !delivered {
+                            tracing::error!("Failed to send output to WebSocket");
+                            finished.push((id, Some(ExitReason::ClientClosed)));
+                        } else if pumped.detached {
+                            finished.push((id, Some(ExitReason::ClientClosed)));
+                        } else if pumped.eof {
+                            finished.push((id, None));
+                        } else {
+                            match open.activity.check(&self.idle) {
+                                IdleCheck::Active => {}
+                                IdleCheck::Warn { seconds_left, kind } => {
+                                    let _ = self.events.blocking_send(ChannelEvent::IdleWarning {
+                                        channel: id,
+                                        seconds_left,
+                                        kind,
+                                    });
+                                }
+                                IdleCheck::Expired(reason) => {
+                                    tracing::info!(
+                                        "Closing terminal {}: {}",
+                                        open.session_id,
+                                        reason
+                                    );
+                                    finished.push((id, Some(reason)));
+                                }
+                            }
+                        }
+                    }
+                    Err(reason) => finished.push((id, Some(reason))),
+                }
+            }
+
+            for (id, reason) in finished {
+                let Some(open) = self.channels.remove(&id) else {
+                    continue;
+                };
+                let _entered = open.span.enter();
+                let reason = match reason {
+                    Some(reason) => {
+                        close_channel(open.channel);
+                        if self.idle.action == IdleAction::Kill
+                            && matches!(reason, ExitReason::IdleTimeout | ExitReason::MaxLifetime)
+                        {
+                            kill_expired_session(session, &open.session_id);
+                        }
+                        reason
+                    }
+                    None => remote_exit(session, open.channel),
+                };
+                self.emit(ChannelEvent::Closed {
+                    channel: id,
+                    reason,
+                });
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+
+    fn handle_command(&mut self, session: &Session, command: ChannelCommand) {
+        match command {
+            ChannelCommand::Open {
+                channel,
+                session_id,
+                cols,
+                rows,
+                input,
+                stats,
+                span,
+            } => {
+                let _entered = span.clone().entered();
+
+                // Channel setup is easier to get right in blocking mode
+                session.set_blocking(true);
+                let opened = open_channel(session, &session_id, cols, rows);
+                session.set_blocking(false);
+
+                match opened {
+                    Ok(ssh_channel) => {
+                        tracing::info!("SSH shell started successfully");
+                        self.channels.insert(
+                            channel,
+                            OpenChannel {
+                                channel: ssh_channel,
+                                input,
+                                session_id,
+                                activity: Activity::new(),
+                                stats,
+                                span,
+                            },
+                        );
+                        self.emit(ChannelEvent::Opened { channel });
+                    }
+                    Err(reason) => {
+                        self.emit(ChannelEvent::Closed { channel, reason });
+                    }
+                }
+            }
+            ChannelCommand::Resize {
+                channel,
+                cols,
+                rows,
+            } => {
+                if let Some(open) = self.channels.get_mut(&channel) {
+                    tracing::info!("Resizing PTY to {}x{}", cols, rows);
+                    if let Err(e) = open.channel.request_pty_size(cols, rows, None, None) {
+                        tracing::error!("Failed to resize PTY: {}", e);
+                    }
+                }
+            }
+            ChannelCommand::Close { channel, reason } => {
+                if let Some(open) = self.channels.remove(&channel) {
+                    let _entered = open.span.enter();
+                    close_channel(open.channel);
+                    self.emit(ChannelEvent::Closed { channel, reason });
+                }
+            }
+        }
+    }
+
+    fn close_all(&mut self) {
+        for (_, open) in self.channels.drain() {
+            close_channel(open.channel);
+        }
+    }
+}
+
+fn close_channel(mut channel: Channel) {
+    let _ = channel.close();
+    let _ = channel.wait_close();
+}
+
+/// `TERMINAL_IDLE_ACTION=kill`: end the tmux session behind an expired terminal
+fn kill_expired_session(session: &Session, session_id: &str) {
+    let mut session = session.clone();
+    session.set_blocking(true);
+    match kill_tmux_session_via_ssh(&mut session, session_id) {
+        Ok(()) => tracing::info!("Killed tmux session {} after expiry", session_id),
+        Err(e) => tracing::warn!("Failed to kill tmux session {}: {}", session_id, e),
+    }
+    session.set_blocking(false);
+}
+
+/// Wait for the remote shell to finish after EOF and translate its exit status
+fn remote_exit(session: &Session, mut channel: Channel) -> ExitReason {
+    // The exit status is only known once the channel is fully closed
+    session.set_blocking(true);
+    let _ = channel.close();
+    let _ = channel.wait_close();
+    let status = channel.exit_status();
+    session.set_blocking(false);
+
+    match status {
+        // "command not found" from the login shell
+        Ok(127) => ExitReason::TmuxMissing,
+        Ok(status) => ExitReason::RemoteExit { status },
+        Err(e) => ExitReason::ssh_error(format!("Failed to read exit status: {}", e)),
+    }
+}
+
+/// Result of one I/O pass over a channel
+struct Pumped {
+    /// Output read from stdout/stderr
+    chunks: Vec<Vec<u8>>,
+    /// The remote side closed its output
+    eof: bool,
+    /// Nobody is left to type into the channel
+    detached: bool,
+    /// Input or output passed through the channel
+    active: bool,
+}
+
+/// Move pending data between one SSH channel and its input queue
+fn pump_channel(open: &mut OpenChannel, buffer: &mut [u8]) -> Result<Pumped, ExitReason> {
+    let mut chunks = Vec::new();
+
+    // Read from SSH stdout
+    match open.channel.read(buffer) {
+        Ok(n) if n > 0 => {
+            tracing::debug!("SSH stdout read {} bytes", n);
+            metrics().terminal_output(n);
+            open.stats.output(n);
+            chunks.push(buffer[..n].to_vec());
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+        Err(e) => {
+            tracing::error!("SSH read error: {}", e);
+            return Err(ExitReason::ssh_error(format!("SSH read error: {}", e)));
+        }
+    }
+
+    // Read from SSH stderr
+    match open.channel.stderr().read(buffer) {
+        Ok(n) if n > 0 => {
+            tracing::debug!("SSH stderr read {} bytes", n);
+            metrics().terminal_output(n);
+            open.stats.output(n);
+            chunks.push(buffer[..n].to_vec());
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+        Err(e) => {
+            tracing::error!("SSH stderr read error: {}", e);
+            return Err(ExitReason::ssh_error(format!(
+                "SSH stderr read error: {}",
+                e
+            )));
+        }
+    }
+
+    // Write keystrokes to SSH
+    let mut wrote = false;
+    match open.input.try_recv() {
+        Ok(data) => {
+            if let Err(e) = open.channel.write_all(&data) {
+                tracing::error!("SSH write error: {}", e);
+                return Err(ExitReason::ssh_error(format!("SSH write error: {}", e)));
+            }
+            let _ = open.channel.flush();
+            metrics().terminal_input(data.len());
+            open.stats.input(data.len());
+            wrote = true;
+        }
+        Err(TryRecvError::Empty) => {}
+        Err(TryRecvError::Disconnected) => {
+            tracing::info!("Terminal input closed");
+            return Ok(Pumped {
+                chunks,
+                eof: false,
+                detached: true,
+                active: false,
+            });
+        }
+    }
+
+    // Check if channel is EOF
+    let eof = open.channel.eof();
+    if eof {
+        tracing::info!("SSH channel EOF");
+    }
+
+    Ok(Pumped {
+        active: wrote || !chunks.is_empty(),
+        chunks,
+        eof,
+        detached: false,
+    })
+}
@@ -0,0 +1,113 @@
+use axum::extract::ws::{CloseFrame, Message};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Why a terminal channel ended, reported to the client before the socket closes.
+///
+/// | reason             | close code | reconnect |
+/// | ------------------ | ---------- | --------- |
+/// | `remote_exit`      | 4000       | no        |
+/// | `auth_failed`      | 4001       | no        |
+/// | `host_unreachable` | 4002       | yes       |
+/// | `ssh_error`        | 4003       | yes       |
+/// | `tmux_missing`     | 4004       | no        |
+/// | `session_revoked`  | 4005       | no        |
+/// | `idle_timeout`     | 4008       | no        |
+/// | `limit_exceeded`   | 4009       | no        |
+/// | `max_lifetime`     | 4010       | no        |
+/// | `terminated`       | 4011       | no        |
+/// | `server_shutdown`  | 1012       | yes       |
+#[derive(Debug, Clone, Serialize, thiserror::Error)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ExitReason {
+    #[error("remote shell exited with status {status}")]
+    RemoteExit { status: i32 },
+    #[error("{message}")]
+    AuthFailed { message: String },
+    #[error("{message}")]
+    HostUnreachable { message: String },
+    #[error("{message}")]
+    SshError { message: String },
+    #[error("tmux is not installed on the SSH server")]
+    TmuxMissing,
+    #[error("you have been logged out")]
+    SessionRevoked,
+    #[error("terminal was idle for too long")]
+    IdleTimeout,
+    #[error("terminal reached its maximum lifetime")]
+    MaxLifetime,
+    #[error("{message}")]
+    LimitExceeded { message: String },
+    /// Closed by an administrator
+    #[error("{message}")]
+    Terminated { message: String },
+    #[error("server is restarting")]
+    ServerShutdown,
+    /// The client went away; nothing is reported
+    #[error("client disconnected")]
+    ClientClosed,
+}
+
+impl ExitReason {
+    pub fn ssh_error(message: impl Into<String>) -> Self {
+        Self::SshError {
+            message: message.into(),
+        }
+    }
+
+    /// WebSocket close code for the reason (4000-4999 is reserved for applications)
+    pub fn close_code(&self) -> u16 {
+        match self {
+            Self::RemoteExit { .. } => 4000,
+            Self::AuthFailed { .. } => 4001,
+            Self::HostUnreachable { .. } => 4002,
+            Self::SshError { .. } => 4003,
+            Self::TmuxMissing => 4004,
+            Self::SessionRevoked => 4005,
+            Self::IdleTimeout => 4008,
+            Self::LimitExceeded { .. } => 4009,
+            Self::MaxLifetime => 4010,
+            Self::Terminated { .. } => 4011,
+            // 1012 = Service Restart
+            Self::ServerShutdown => 1012,
+            Self::ClientClosed => 1000,
+        }
+    }
+
+    /// Whether the client should reconnect on its own
+    pub fn reconnect(&self) -> bool {
+        matches!(
+            self,
+            Self::HostUnreachable { .. } | Self::SshError { .. } | Self::ServerShutdown
+        )
+    }
+
+    /// Reason, message, close code and reconnect hint as a JSON object
+    pub fn details(&self) -> Value {
+        let mut details = serde_json::to_value(self).unwrap_or_default();
+        details["message"] = json!(self.to_string());
+        details["code"] = json!(self.close_code());
+        details["reconnect"] = json!(self.reconnect());
+        details
+    }
+
+    /// `exit` control frame describing the reason
+    pub fn frame(&self) -> Message {
+        let mut frame = self.details();
+        frame["type"] = json!("exit");
+        Message::Text(frame.to_string())
+    }
+
+    /// Close frame carrying the matching close code
+    pub fn close_frame(&self) -> Message {
+        let reason = serde_json::to_value(self)
+            .ok()
+            .and_then(|v| v["reason"].as_str().map(str::to_string))
+            .unwrap_or_default();
+
+        Message::Close(Some(CloseFrame {
+            code: self.close_code(),
+            reason: reason.into(),
+        }))
+    }
+}
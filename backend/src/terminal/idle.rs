@@ -0,0 +1,268 @@
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::exit::ExitReason;
+use crate::config::IdleConfig;
+
+/// What happens to the tmux session when a terminal is disconnected for inactivity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdleAction {
+    /// Close the SSH channel; the tmux session keeps running and can be reattached
+    Detach,
+    /// Kill the tmux session as well
+    Kill,
+}
+
+impl FromStr for IdleAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "detach" => Ok(Self::Detach),
+            "kill" => Ok(Self::Kill),
+            _ => Err("expected `detach` or `kill`".to_string()),
+        }
+    }
+}
+
+/// Why a terminal is about to be disconnected
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryKind {
+    Idle,
+    MaxLifetime,
+}
+
+/// Inactivity and lifetime limits applied to every terminal channel (`[terminal.idle]`)
+#[derive(Debug, Clone, Copy)]
+pub struct IdlePolicy {
+    pub timeout: Option<Duration>,
+    pub warning: Duration,
+    pub action: IdleAction,
+    pub max_lifetime: Option<Duration>,
+}
+
+impl IdlePolicy {
+    pub fn new(config: &IdleConfig) -> Self {
+        Self {
+            timeout: config.timeout.map(Duration::from_secs),
+            warning: Duration::from_secs(config.warning),
+            action: config.action,
+            max_lifetime: config.max_lifetime.map(Duration::from_secs),
+        }
+    }
+
+    /// `idle_warning` control frame; `kill` tells the client the tmux session goes away too
+    pub fn warning_frame(&self, seconds_left: u64, kind: ExpiryKind) -> Value {
+        let action = match self.action {
+            IdleAction::Detach => "detach",
+            IdleAction::Kill => "kill",
+        };
+        json!({
+            "type": "idle_warning",
+            "reason": kind,
+            "seconds_left": seconds_left,
+            "action": action,
+        })
+    }
+
+    /// Time left for a channel and what ends it first, `None` when nothing applies
+    fn remaining(&self, activity: &Activity, now: Instant) -> Option<(Duration, ExpiryKind)> {
+        let idle = self.timeout.map(|t| {
+            (
+                (activity.last + t).saturating_duration_since(now),
+                ExpiryKind::Idle,
+            )
+        });
+        let lifetime = self.max_lifetime.map(|t| {
+            (
+                (activity.opened + t).saturating_duration_since(now),
+                ExpiryKind::MaxLifetime,
+            )
+        });
+
+        match (idle, lifetime) {
+            (Some(a), Some(b)) => Some(if b.0 < a.0 { b } else { a }),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+/// Result of checking a channel against the policy
+pub enum IdleCheck {
+    Active,
+    /// Send a warning once; the channel closes after `seconds_left`
+    Warn {
+        seconds_left: u64,
+        kind: ExpiryKind,
+    },
+    Expired(ExitReason),
+}
+
+/// Activity timestamps of one terminal channel
+pub struct Activity {
+    opened: Instant,
+    last: Instant,
+    /// Deadline a warning has already been sent for
+    warned: Option<ExpiryKind>,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            opened: now,
+            last: now,
+            warned: None,
+        }
+    }
+
+    /// Input or output passed through the channel
+    pub fn touch(&mut self) {
+        self.last = Instant::now();
+        // Activity postpones the idle deadline, not the lifetime one
+        if self.warned == Some(ExpiryKind::Idle) {
+            self.warned = None;
+        }
+    }
+
+    pub fn check(&mut self, policy: &IdlePolicy) -> IdleCheck {
+        let Some((left, kind)) = policy.remaining(self, Instant::now()) else {
+            return IdleCheck::Active;
+        };
+
+        if left.is_zero() {
+            return IdleCheck::Expired(match kind {
+                ExpiryKind::Idle => ExitReason::IdleTimeout,
+                ExpiryKind::MaxLifetime => ExitReason::MaxLifetime,
+            });
+        }
+        if left <= policy.warning && self.warned != Some(kind) {
+            self.warned = Some(kind);
+            return IdleCheck::Warn {
+                seconds_left: left.as_secs().max(1),
+                kind,
+            };
+        }
+
+        IdleCheck::Active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ten seconds of warning before either deadline
+    fn policy(timeout: Option<u64>, max_lifetime: Option<u64>) -> IdlePolicy {
+        IdlePolicy::new(&IdleConfig {
+            timeout,
+            warning: 10,
+            action: IdleAction::Detach,
+            max_lifetime,
+        })
+    }
+
+    /// Activity of a channel opened `opened` seconds ago and quiet for `idle` seconds
+    fn activity(opened: u64, idle: u64) -> Activity {
+        let now = Instant::now();
+        Activity {
+            opened: now - Duration::from_secs(opened),
+            last: now - Duration::from_secs(idle),
+            warned: None,
+        }
+    }
+
+    fn warned(check: IdleCheck) -> Option<ExpiryKind> {
+        match check {
+            IdleCheck::Warn { seconds_left, kind } => {
+                assert!((1..=10).contains(&seconds_left));
+                Some(kind)
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn nothing_configured_never_expires() {
+        let mut activity = activity(200, 200);
+        assert!(matches!(
+            activity.check(&policy(None, None)),
+            IdleCheck::Active
+        ));
+    }
+
+    #[test]
+    fn warns_once_per_deadline() {
+        let policy = policy(Some(100), None);
+        let mut activity = activity(95, 95);
+
+        assert_eq!(warned(activity.check(&policy)), Some(ExpiryKind::Idle));
+        assert!(matches!(activity.check(&policy), IdleCheck::Active));
+    }
+
+    #[test]
+    fn touch_clears_only_an_idle_warning() {
+        let policy = policy(Some(100), None);
+        let mut activity = activity(95, 95);
+        assert_eq!(warned(activity.check(&policy)), Some(ExpiryKind::Idle));
+
+        // Typing postpones the idle deadline, which then warns again when it comes near
+        activity.touch();
+        assert!(matches!(activity.check(&policy), IdleCheck::Active));
+        activity.last -= Duration::from_secs(95);
+        assert_eq!(warned(activity.check(&policy)), Some(ExpiryKind::Idle));
+
+        // The lifetime deadline does not move, so its warning is not repeated
+        let policy = IdlePolicy {
+            timeout: None,
+            max_lifetime: Some(Duration::from_secs(100)),
+            ..policy
+        };
+        let mut activity = self::activity(95, 0);
+        assert_eq!(
+            warned(activity.check(&policy)),
+            Some(ExpiryKind::MaxLifetime)
+        );
+        activity.touch();
+        assert!(matches!(activity.check(&policy), IdleCheck::Active));
+    }
+
+    #[test]
+    fn the_nearer_deadline_wins() {
+        // Busy but about to reach its lifetime
+        let mut activity = activity(95, 0);
+        assert_eq!(
+            warned(activity.check(&policy(Some(100), Some(100)))),
+            Some(ExpiryKind::MaxLifetime)
+        );
+
+        // Young but quiet
+        let mut activity = self::activity(10, 95);
+        assert_eq!(
+            warned(activity.check(&policy(Some(100), Some(1000)))),
+            Some(ExpiryKind::Idle)
+        );
+    }
+
+    #[test]
+    fn expired_deadlines_end_the_channel() {
+        let mut idle = activity(100, 100);
+        assert!(matches!(
+            idle.check(&policy(Some(100), None)),
+            IdleCheck::Expired(ExitReason::IdleTimeout)
+        ));
+
+        let mut old = activity(200, 0);
+        assert!(matches!(
+            old.check(&policy(Some(100), Some(150))),
+            IdleCheck::Expired(ExitReason::MaxLifetime)
+        ));
+    }
+}
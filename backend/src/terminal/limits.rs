@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{config::TerminalConfig, ssh::SshTarget};
+
+/// A configured limit that a new terminal or tmux session would exceed
+#[derive(Debug, Clone, Serialize, thiserror::Error)]
+#[serde(tag = "limit", rename_all = "snake_case")]
+pub enum LimitExceeded {
+    #[error("you already have {max} open terminals")]
+    UserTerminals { max: usize },
+    #[error("{host} already has {max} open terminals")]
+    HostTerminals { host: String, max: usize },
+    #[error("you already have {max} tmux sessions")]
+    TmuxSessions { max: usize },
+}
+
+impl IntoResponse for LimitExceeded {
+    fn into_response(self) -> Response {
+        let mut body = serde_json::to_value(&self).unwrap_or_default();
+        body["error"] = json!("limit_exceeded");
+        body["message"] = json!(self.to_string());
+
+        (StatusCode::TOO_MANY_REQUESTS, Json(body)).into_response()
+    }
+}
+
+/// Open terminal of one user on one host
+#[derive(Debug)]
+struct LiveTerminal {
+    username: String,
+    target: SshTarget,
+}
+
+/// Live terminal counts, checked against the `[terminal]` limits (unset = unlimited)
+#[derive(Debug, Default)]
+pub struct SessionLimits {
+    live: Mutex<HashMap<Uuid, LiveTerminal>>,
+}
+
+impl SessionLimits {
+    /// Reserve a slot for a new terminal; it is released when the returned guard is dropped
+    pub fn acquire(
+        self: &Arc<Self>,
+        config: &TerminalConfig,
+        username: &str,
+        target: &SshTarget,
+    ) -> Result<TerminalSlot, LimitExceeded> {
+        let mut live = self.live.lock().unwrap();
+
+        if let Some(max) = config.max_sessions_per_user {
+            if live.values().filter(|t| t.username == username).count() >= max {
+                return Err(LimitExceeded::UserTerminals { max });
+            }
+        }
+        if let Some(max) = config.max_sessions_per_host {
+            if live.values().filter(|t| t.target == *target).count() >= max {
+                return Err(LimitExceeded::HostTerminals {
+                    host: target.to_string(),
+                    max,
+                });
+            }
+        }
+
+        let id = Uuid::new_v4();
+        live.insert(
+            id,
+            LiveTerminal {
+                username: username.to_string(),
+                target: target.clone(),
+            },
+        );
+
+        Ok(TerminalSlot {
+            limits: Arc::clone(self),
+            id,
+        })
+    }
+
+    /// Whether one more tmux session fits next to `existing`
+    pub fn check_tmux(config: &TerminalConfig, existing: usize) -> Result<(), LimitExceeded> {
+        match config.max_tmux_sessions_per_user {
+            Some(max) if existing >= max => Err(LimitExceeded::TmuxSessions { max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Open terminals of a user per host, and of all users on those hosts
+    pub fn usage(&self, username: &str) -> Vec<(SshTarget, usize, usize)> {
+        let live = self.live.lock().unwrap();
+        let mut hosts: Vec<(SshTarget, usize, usize)> = Vec::new();
+
+        for terminal in live.values().filter(|t| t.username == username) {
+            match hosts
+                .iter_mut()
+                .find(|(target, _, _)| *target == terminal.target)
+            {
+                Some((_, used, _)) => *used += 1,
+                None => {
+                    let total = live
+                        .values()
+                        .filter(|t| t.target == terminal.target)
+                        .count();
+                    hosts.push((terminal.target.clone(), 1, total));
+                }
+            }
+        }
+
+        hosts
+    }
+
+    fn release(&self, id: &Uuid) {
+        self.live.lock().unwrap().remove(id);
+    }
+}
+
+/// Counts one terminal against the limits for as long as it is alive
+pub struct TerminalSlot {
+    limits: Arc<SessionLimits>,
+    id: Uuid,
+}
+
+impl Drop for TerminalSlot {
+    fn drop(&mut self) {
+        self.limits.release(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(per_user: Option<usize>, per_host: Option<usize>) -> TerminalConfig {
+        TerminalConfig {
+            max_sessions_per_user: per_user,
+            max_sessions_per_host: per_host,
+            max_tmux_sessions_per_user: Some(2),
+            ..TerminalConfig::default()
+        }
+    }
+
+    fn host(name: &str) -> SshTarget {
+        SshTarget {
+            host: name.to_string(),
+            port: 22,
+        }
+    }
+
+    #[test]
+    fn limits_terminals_per_user() {
+        let limits = Arc::new(SessionLimits::default());
+        let config = config(Some(2), None);
+        let _first = limits.acquire(&config, "alice", &host("web-1")).unwrap();
+        let _second = limits.acquire(&config, "alice", &host("web-2")).unwrap();
+
+        assert!(matches!(
+            limits.acquire(&config, "alice", &host("web-3")),
+            Err(LimitExceeded::UserTerminals { max: 2 })
+        ));
+        assert!(limits.acquire(&config, "bob", &host("web-1")).is_ok());
+    }
+
+    #[test]
+    fn limits_terminals_per_host() {
+        let limits = Arc::new(SessionLimits::default());
+        let config = config(None, Some(1));
+        let _alice = limits.acquire(&config, "alice", &host("web-1")).unwrap();
+
+        let error = limits
+            .acquire(&config, "bob", &host("web-1"))
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "web-1:22 already has 1 open terminals");
+        assert!(limits.acquire(&config, "bob", &host("web-2")).is_ok());
+    }
+
+    #[test]
+    fn dropping_the_slot_releases_it() {
+        let limits = Arc::new(SessionLimits::default());
+        let config = config(Some(1), Some(1));
+        let slot = limits.acquire(&config, "alice", &host("web-1")).unwrap();
+        assert!(limits.acquire(&config, "alice", &host("web-1")).is_err());
+
+        drop(slot);
+        assert!(limits.usage("alice").is_empty());
+        assert!(limits.acquire(&config, "alice", &host("web-1")).is_ok());
+    }
+
+    #[test]
+    fn unset_limits_are_unlimited() {
+        let limits = Arc::new(SessionLimits::default());
+        let config = TerminalConfig::default();
+        let slots: Vec<_> = (0..50)
+            .map(|_| limits.acquire(&config, "alice", &host("web-1")).unwrap())
+            .collect();
+        assert_eq!(limits.usage("alice"), vec![(host("web-1"), 50, 50)]);
+        drop(slots);
+    }
+
+    #[test]
+    fn usage_counts_the_user_and_everyone_per_host() {
+        let limits = Arc::new(SessionLimits::default());
+        let config = config(None, None);
+        let _slots = [
+            limits.acquire(&config, "alice", &host("web-1")).unwrap(),
+            limits.acquire(&config, "alice", &host("web-1")).unwrap(),
+            limits.acquire(&config, "bob", &host("web-1")).unwrap(),
+            limits.acquire(&config, "bob", &host("web-2")).unwrap(),
+        ];
+
+        assert_eq!(limits.usage("alice"), vec![(host("web-1"), 2, 3)]);
+    }
+
+    #[test]
+    fn tmux_sessions_are_checked_against_the_existing_count() {
+        let config = config(None, None);
+        assert!(SessionLimits::check_tmux(&config, 1).is_ok());
+        assert!(matches!(
+            SessionLimits::check_tmux(&config, 2),
+            Err(LimitExceeded::TmuxSessions { max: 2 })
+        ));
+        assert!(SessionLimits::check_tmux(&TerminalConfig::default(), 1000).is_ok());
+    }
+}
@@ -1,9 +1,21 @@
+pub mod broadcast;
+pub mod collab;
+pub mod connection;
+pub mod exit;
+pub mod idle;
+pub mod limits;
+pub mod reaper;
+pub mod registry;
+pub mod share;
+
 use anyhow::{anyhow, Result};
 use portable_pty::{CommandBuilder, NativePtySystem, PtySize, PtySystem};
 use serde::{Deserialize, Serialize};
 use ssh2::Session;
 use std::io::Read;
 
+use crate::metrics::metrics;
+
 #[allow(dead_code)]
 pub struct Terminal {
     _pty_system: NativePtySystem,
@@ -42,25 +54,54 @@ pub struct TmuxSession {
     pub windows: u32,
     pub created: String,
     pub attached: bool,
+    /// Unix time of the last input or output in the session
+    pub activity: String,
+    /// Pinned with `@deuseda_keep`; never reaped
+    pub keep_alive: bool,
+}
+
+/// Longest tmux session name accepted from clients
+const MAX_SESSION_NAME_LEN: usize = 64;
+
+/// Whether a tmux session name is `[A-Za-z0-9_-]+`. Names are put into shell commands
+/// on the SSH host, so nothing else is accepted.
+pub fn is_valid_session_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_SESSION_NAME_LEN
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+/// Refuse to build a command from a name that did not pass `is_valid_session_name`
+fn checked_session_name(name: &str) -> Result<&str> {
+    if is_valid_session_name(name) {
+        Ok(name)
+    } else {
+        Err(anyhow!("Invalid tmux session name {:?}", name))
+    }
 }
 
 /// List tmux sessions via SSH
+#[tracing::instrument(skip(session))]
 pub fn list_tmux_sessions_via_ssh(session: &mut Session) -> Result<Vec<TmuxSession>> {
-    let mut channel = session.channel_session()?;
+    metrics().tmux("list_tmux_sessions_via_ssh", || {
+        let mut channel = session.channel_session()?;
 
-    // Execute tmux list-sessions command
-    // Format: session_name:windows:created:attached
-    channel.exec("tmux list-sessions -F '#{session_name}:#{session_windows}:#{session_created}:#{session_attached}' 2>/dev/null || echo 'NO_SESSIONS'")?;
+        // Execute tmux list-sessions command
+        // Format: session_name:windows:created:attached:activity:keep_alive
+        channel.exec("tmux list-sessions -F '#{session_name}:#{session_windows}:#{session_created}:#{session_attached}:#{session_activity}:#{@deuseda_keep}' 2>/dev/null || echo 'NO_SESSIONS'")?;
 
-    let mut output = String::new();
-    channel.read_to_string(&mut output)?;
-    channel.wait_close()?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.wait_close()?;
 
-    if output.trim() == "NO_SESSIONS" || output.is_empty() {
-        return Ok(vec![]);
-    }
+        if output.trim() == "NO_SESSIONS" || output.is_empty() {
+            return Ok(vec![]);
+        }
 
-    parse_tmux_session_list(&output)
+        parse_tmux_session_list(&output)
+    })
 }
 
 /// Parse tmux list-sessions output
@@ -79,7 +120,10 @@ fn parse_tmux_session_list(output: &str) -> Result<Vec<TmuxSession>> {
                 name: parts[0].to_string(),
                 windows: parts[1].parse().unwrap_or(1),
                 created: parts[2].to_string(),
-                attached: parts[3] == "1",
+                // Number of attached clients
+                attached: parts[3].parse::<u32>().unwrap_or(0) > 0,
+                activity: parts.get(4).unwrap_or(&"").to_string(),
+                keep_alive: parts.get(5) == Some(&"1"),
             });
         }
     }
@@ -88,55 +132,123 @@ fn parse_tmux_session_list(output: &str) -> Result<Vec<TmuxSession>> {
 }
 
 /// Create a new tmux session via SSH
+#[tracing::instrument(skip(session))]
 pub fn create_tmux_session_via_ssh(session: &mut Session, session_name: &str) -> Result<()> {
-    let mut channel = session.channel_session()?;
+    let session_name = checked_session_name(session_name)?;
+    metrics().tmux("create_tmux_session_via_ssh", || {
+        let mut channel = session.channel_session()?;
 
-    // Create detached tmux session
-    let command = format!("tmux new-session -d -s '{}'", session_name);
-    channel.exec(&command)?;
+        // Create detached tmux session
+        let command = format!("tmux new-session -d -s '{}'", session_name);
+        channel.exec(&command)?;
 
-    let mut output = String::new();
-    channel.read_to_string(&mut output)?;
-    channel.wait_close()?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.wait_close()?;
 
-    let exit_status = channel.exit_status()?;
-    if exit_status != 0 {
-        return Err(anyhow!("Failed to create tmux session: {}", output));
-    }
+        let exit_status = channel.exit_status()?;
+        if exit_status != 0 {
+            return Err(anyhow!("Failed to create tmux session: {}", output));
+        }
 
-    Ok(())
+        Ok(())
+    })
 }
 
 /// Check if a tmux session exists
+#[tracing::instrument(skip(session))]
 pub fn tmux_session_exists_via_ssh(session: &mut Session, session_name: &str) -> Result<bool> {
-    let mut channel = session.channel_session()?;
+    let session_name = checked_session_name(session_name)?;
+    metrics().tmux("tmux_session_exists_via_ssh", || {
+        let mut channel = session.channel_session()?;
 
-    let command = format!("tmux has-session -t '{}' 2>/dev/null", session_name);
-    channel.exec(&command)?;
+        let command = format!("tmux has-session -t '{}' 2>/dev/null", session_name);
+        channel.exec(&command)?;
 
-    let mut _output = String::new();
-    channel.read_to_string(&mut _output)?;
-    channel.wait_close()?;
+        let mut _output = String::new();
+        channel.read_to_string(&mut _output)?;
+        channel.wait_close()?;
 
-    let exit_status = channel.exit_status()?;
-    Ok(exit_status == 0)
+        let exit_status = channel.exit_status()?;
+        Ok(exit_status == 0)
+    })
 }
 
 /// Kill a tmux session via SSH
+#[tracing::instrument(skip(session))]
 pub fn kill_tmux_session_via_ssh(session: &mut Session, session_name: &str) -> Result<()> {
-    let mut channel = session.channel_session()?;
+    let session_name = checked_session_name(session_name)?;
+    metrics().tmux("kill_tmux_session_via_ssh", || {
+        let mut channel = session.channel_session()?;
 
-    let command = format!("tmux kill-session -t '{}'", session_name);
-    channel.exec(&command)?;
+        let command = format!("tmux kill-session -t '{}'", session_name);
+        channel.exec(&command)?;
 
-    let mut output = String::new();
-    channel.read_to_string(&mut output)?;
-    channel.wait_close()?;
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.wait_close()?;
 
-    let exit_status = channel.exit_status()?;
-    if exit_status != 0 {
-        return Err(anyhow!("Failed to kill tmux session: {}", output));
-    }
+        let exit_status = channel.exit_status()?;
+        if exit_status != 0 {
+            return Err(anyhow!("Failed to kill tmux session: {}", output));
+        }
+
+        Ok(())
+    })
+}
 
-    Ok(())
+/// Pin a tmux session so the reaper leaves it alone, or unpin it
+#[tracing::instrument(skip(session))]
+pub fn set_tmux_keep_alive_via_ssh(
+    session: &mut Session,
+    session_name: &str,
+    keep_alive: bool,
+) -> Result<()> {
+    let session_name = checked_session_name(session_name)?;
+    metrics().tmux("set_tmux_keep_alive_via_ssh", || {
+        let mut channel = session.channel_session()?;
+
+        // Stored as a tmux user option, so the pin survives backend restarts
+        let command = if keep_alive {
+            format!("tmux set-option -t '{}' @deuseda_keep 1", session_name)
+        } else {
+            format!("tmux set-option -u -t '{}' @deuseda_keep", session_name)
+        };
+        channel.exec(&command)?;
+
+        let mut output = String::new();
+        channel.read_to_string(&mut output)?;
+        channel.wait_close()?;
+
+        let exit_status = channel.exit_status()?;
+        if exit_status != 0 {
+            return Err(anyhow!("Failed to update tmux session: {}", output));
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_names_are_shell_safe() {
+        for name in ["default", "1718000000000-abc123", "my_session", "0"] {
+            assert!(is_valid_session_name(name), "{name}");
+        }
+        for name in [
+            "",
+            "x'; rm -rf ~; '",
+            "a b",
+            "a:b",
+            "a.b",
+            "$(id)",
+            &"a".repeat(65),
+        ] {
+            assert!(!is_valid_session_name(name), "{name}");
+        }
+        assert!(checked_session_name("x'y").is_err());
+    }
 }
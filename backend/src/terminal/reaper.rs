@@ -0,0 +1,169 @@
+use std::{collections::HashSet, time::Duration};
+
+use anyhow::{anyhow, Result};
+use tokio::time::Instant;
+
+use super::{connection, kill_tmux_session_via_ssh, list_tmux_sessions_via_ssh, TmuxSession};
+use crate::{
+    config::Config,
+    ssh::{SshAuth, SshTarget},
+    state::AppState,
+    telemetry,
+};
+
+/// Periodic cleanup of tmux sessions nobody has used for a long time (`[reaper]`)
+///
+/// Attached sessions, sessions with an open terminal and pinned sessions are never reaped.
+/// The server connects with its own credentials, so the SSH CA or SSO user keys are required.
+/// Users are only probed on hosts they opened terminals or created sessions on since startup;
+/// `users` from the config are probed on every allowed host.
+/// `max_idle`, `interval` and `users` are re-read after every sweep and reload, so a
+/// reload can turn the reaper on or off.
+#[derive(Debug, Clone, Copy)]
+pub struct TmuxReaper;
+
+impl TmuxReaper {
+    /// Sweep every `interval` for as long as the server runs; idle while `max_idle` is unset
+    pub fn spawn(state: AppState) {
+        tokio::spawn(async move {
+            let mut reloads = state.config.subscribe();
+            let mut enabled = false;
+            let mut last_sweep = Instant::now();
+            loop {
+                let config = state.config.get();
+                let interval = Duration::from_secs(config.reaper.interval);
+                if config.reaper.max_idle.is_some() != enabled {
+                    enabled = !enabled;
+                    Self::log_state(&state, &config, enabled, interval);
+                }
+                drop(config);
+
+                tokio::select! {
+                    _ = tokio::time::sleep_until(last_sweep + interval) => {
+                        last_sweep = Instant::now();
+                        if enabled {
+                            Self::sweep(&state).await;
+                        }
+                    }
+                    // Pick up a new interval or `max_idle` without waiting out the old one
+                    changed = reloads.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    fn log_state(state: &AppState, config: &Config, enabled: bool, interval: Duration) {
+        if !enabled {
+            tracing::info!("tmux reaper disabled");
+            return;
+        }
+        tracing::info!("tmux reaper enabled (every {:?})", interval);
+        if state.ssh_ca.is_none() && config.ssh.user_key_dir.is_none() {
+            tracing::warn!(
+                "tmux reaper has no SSH credentials of its own (set ssh.ca or ssh.user_key_dir); \
+                 no session will be reaped"
+            );
+        }
+    }
+
+    async fn sweep(state: &AppState) {
+        let config = state.config.get();
+        let Some(max_idle) = config.reaper.max_idle else {
+            return;
+        };
+
+        // Each user only on the hosts they have had sessions on
+        let allowed = config.ssh.allowed_targets();
+        let mut sweeps = HashSet::new();
+        for user in state.tokens.known_users() {
+            for target in state.bridges.session_hosts(&user) {
+                if allowed.contains(&target) {
+                    sweeps.insert((user.clone(), target));
+                }
+            }
+        }
+        for user in &config.reaper.users {
+            for target in &allowed {
+                sweeps.insert((user.clone(), target.clone()));
+            }
+        }
+
+        for (username, target) in sweeps {
+            if let Err(e) = Self::sweep_host(state, &config, max_idle, &username, &target).await {
+                tracing::warn!("tmux reaper failed for {} on {}: {:#}", username, target, e);
+            }
+        }
+    }
+
+    #[tracing::instrument(name = "tmux_reaper_sweep", skip_all, fields(user = username, host = %target))]
+    async fn sweep_host(
+        state: &AppState,
+        config: &Config,
+        max_idle: u64,
+        username: &str,
+        target: &SshTarget,
+    ) -> Result<()> {
+        // A fresh certificate per connection, or the user's SSO key
+        let auth = match SshAuth::from_server(&config.ssh, state.ssh_ca.as_deref(), username, true)
+        {
+            Ok(Some(auth)) => auth,
+            _ => {
+                tracing::debug!("tmux reaper has no SSH credentials for {}", username);
+                return Ok(());
+            }
+        };
+
+        let bridges = state.bridges.clone();
+        let (user, host) = (username.to_string(), target.clone());
+
+        let reaped = telemetry::spawn_blocking(move || -> Result<Vec<TmuxSession>> {
+            let mut session = connection::connect(&host, &user, &auth).map_err(|e| anyhow!(e))?;
+            let now = chrono::Utc::now().timestamp();
+
+            let mut reaped = Vec::new();
+            for tmux in list_tmux_sessions_via_ssh(&mut session)? {
+                let idle_secs = tmux
+                    .activity
+                    .parse::<i64>()
+                    .map(|activity| now - activity)
+                    .unwrap_or(0);
+                let expired = idle_secs > max_idle as i64;
+                let in_use = tmux.attached
+                    || tmux.keep_alive
+                    || bridges.find_session(&user, &host, &tmux.name).is_some();
+
+                if !expired || in_use {
+                    continue;
+                }
+                match kill_tmux_session_via_ssh(&mut session, &tmux.name) {
+                    Ok(()) => reaped.push(tmux),
+                    Err(e) => tracing::warn!("Failed to reap tmux session {}: {}", tmux.name, e),
+                }
+            }
+            Ok(reaped)
+        })
+        .await??;
+
+        for tmux in reaped {
+            tracing::info!(
+                target: "deuseda::audit",
+                event = "tmux_reaped",
+                username = %username,
+                host = %target,
+                session = %tmux.name,
+                created = %tmux.created,
+                last_activity = %tmux.activity,
+                "Reaped tmux session {} of {} on {}",
+                tmux.name,
+                username,
+                target
+            );
+        }
+
+        Ok(())
+    }
+}
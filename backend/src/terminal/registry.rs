@@ -0,0 +1,270 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc, watch};
+use uuid::Uuid;
+
+use super::{
+    collab::{Collaboration, PresenceEntry},
+    share::ShareScope,
+};
+use crate::{config::ServerConfig, middleware::client_ip::client_ip, ssh::SshTarget};
+
+/// Control notification delivered to every socket attached to a bridge as a JSON text frame
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BridgeEvent {
+    ViewerJoined { viewer: String, scope: ShareScope },
+    ViewerLeft { viewer: String },
+    Presence { participants: Vec<PresenceEntry> },
+    ControlRequested { from: String },
+    ServerRestarting { seconds_left: u64, message: String },
+}
+
+/// Where a terminal connection comes from
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub ip: IpAddr,
+    pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn from_request(headers: &HeaderMap, peer: SocketAddr, server: &ServerConfig) -> Self {
+        Self {
+            ip: client_ip(headers, peer, server),
+            user_agent: headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+/// Traffic of a bridge, counted by the SSH worker
+#[derive(Debug)]
+pub struct BridgeStats {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    /// Unix time of the last keystroke or output
+    last_activity: AtomicI64,
+}
+
+impl BridgeStats {
+    fn new() -> Self {
+        Self {
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            last_activity: AtomicI64::new(Utc::now().timestamp()),
+        }
+    }
+
+    pub fn input(&self, bytes: usize) {
+        self.bytes_in.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    pub fn output(&self, bytes: usize) {
+        self.bytes_out.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    fn touch(&self) {
+        self.last_activity
+            .store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    pub fn last_activity(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp(self.last_activity.load(Ordering::Relaxed), 0).unwrap_or_default()
+    }
+}
+
+/// Live `/ws/terminal` bridge that other features (broadcast, sharing, ...) can reach
+#[derive(Debug, Clone)]
+pub struct BridgeHandle {
+    pub id: Uuid,
+    pub username: String,
+    pub target: SshTarget,
+    pub session_id: String,
+    pub client: ClientInfo,
+    pub started_at: DateTime<Utc>,
+    pub stats: Arc<BridgeStats>,
+    /// Set by an administrator to end the connection, with the message for the client
+    terminate: watch::Sender<Option<String>>,
+    /// Keystrokes written into the bridge's SSH channel
+    pub input: mpsc::Sender<Vec<u8>>,
+    /// Copy of everything the SSH channel prints, for viewers
+    pub output: broadcast::Sender<Vec<u8>>,
+    /// Notifications for the owner and everyone watching
+    pub events: broadcast::Sender<BridgeEvent>,
+    /// Keyboard control between the owner and collaborators
+    pub collab: Arc<Collaboration>,
+}
+
+impl BridgeHandle {
+    /// Create the channels of a new bridge; the owner's connection starts out holding the keyboard.
+    /// Returns the receiving end of `input` for the SSH channel.
+    pub fn new(
+        username: &str,
+        target: &SshTarget,
+        session_id: &str,
+        client: ClientInfo,
+    ) -> (Self, mpsc::Receiver<Vec<u8>>) {
+        let id = Uuid::new_v4();
+        let (input, rx_input) = mpsc::channel::<Vec<u8>>(100);
+        let (output, _) = broadcast::channel::<Vec<u8>>(256);
+        let (events, _) = broadcast::channel::<BridgeEvent>(64);
+
+        let handle = Self {
+            id,
+            username: username.to_string(),
+            target: target.clone(),
+            session_id: session_id.to_string(),
+            client,
+            started_at: Utc::now(),
+            stats: Arc::new(BridgeStats::new()),
+            terminate: watch::Sender::new(None),
+            input,
+            output,
+            events: events.clone(),
+            collab: Arc::new(Collaboration::new(id, username, events)),
+        };
+
+        (handle, rx_input)
+    }
+
+    /// Ask the connection to close, telling the client `message`
+    pub fn terminate(&self, message: &str) {
+        self.terminate.send_replace(Some(message.to_string()));
+    }
+
+    /// Resolves with the message once `terminate` was called
+    pub fn terminated(&self) -> impl Future<Output = String> + Send + 'static {
+        let mut terminate = self.terminate.subscribe();
+        async move {
+            let message = match terminate.wait_for(Option::is_some).await {
+                Ok(message) => message.clone(),
+                Err(_) => None,
+            };
+            match message {
+                Some(message) => message,
+                // Every handle is gone, so nobody can terminate the bridge anymore
+                None => std::future::pending().await,
+            }
+        }
+    }
+}
+
+/// In-memory registry of active terminal bridges
+#[derive(Debug, Default)]
+pub struct BridgeRegistry {
+    bridges: RwLock<HashMap<Uuid, BridgeHandle>>,
+    /// Hosts each user has had tmux sessions on since startup, for the tmux reaper
+    session_hosts: RwLock<HashMap<String, HashSet<SshTarget>>>,
+}
+
+impl BridgeRegistry {
+    /// Register a bridge; it is removed again when the returned guard is dropped
+    pub fn register(self: &Arc<Self>, handle: BridgeHandle) -> BridgeGuard {
+        let id = handle.id;
+        self.record_session_host(&handle.username, &handle.target);
+        self.bridges.write().unwrap().insert(id, handle);
+
+        BridgeGuard {
+            registry: Arc::clone(self),
+            id,
+        }
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<BridgeHandle> {
+        self.bridges.read().unwrap().get(id).cloned()
+    }
+
+    /// Remember that `username` has a tmux session on `target`
+    pub fn record_session_host(&self, username: &str, target: &SshTarget) {
+        self.session_hosts
+            .write()
+            .unwrap()
+            .entry(username.to_string())
+            .or_default()
+            .insert(target.clone());
+    }
+
+    /// Hosts `username` has had tmux sessions on since startup
+    pub fn session_hosts(&self, username: &str) -> Vec<SshTarget> {
+        self.session_hosts
+            .read()
+            .unwrap()
+            .get(username)
+            .map(|hosts| hosts.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Every live bridge
+    pub fn all(&self) -> Vec<BridgeHandle> {
+        self.bridges.read().unwrap().values().cloned().collect()
+    }
+
+    pub fn count(&self) -> usize {
+        self.bridges.read().unwrap().len()
+    }
+
+    /// Bridges owned by `username`
+    pub fn of_user(&self, username: &str) -> Vec<BridgeHandle> {
+        self.bridges
+            .read()
+            .unwrap()
+            .values()
+            .filter(|b| b.username == username)
+            .cloned()
+            .collect()
+    }
+
+    /// First bridge owned by `username` attached to the given tmux session
+    pub fn find_session(
+        &self,
+        username: &str,
+        target: &SshTarget,
+        session_id: &str,
+    ) -> Option<BridgeHandle> {
+        self.bridges
+            .read()
+            .unwrap()
+            .values()
+            .find(|b| b.username == username && b.target == *target && b.session_id == session_id)
+            .cloned()
+    }
+
+    fn remove(&self, id: &Uuid) {
+        self.bridges.write().unwrap().remove(id);
+    }
+}
+
+/// Keeps a bridge registered for as long as it is alive
+pub struct BridgeGuard {
+    registry: Arc<BridgeRegistry>,
+    id: Uuid,
+}
+
+impl Drop for BridgeGuard {
+    fn drop(&mut self) {
+        self.registry.remove(&self.id);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// What a share link allows the viewer to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareScope {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Expiring capability to watch (or type into) someone else's live terminal
+#[derive(Debug, Clone)]
+pub struct ShareLink {
+    pub token: String,
+    pub bridge_id: Uuid,
+    pub owner: String,
+    pub session_id: String,
+    pub scope: ShareScope,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ShareLink {
+    pub fn is_expired(&self) -> bool {
+        Utc::now() >= self.expires_at
+    }
+}
+
+/// Share links minted by bridge owners
+#[derive(Debug, Default)]
+pub struct ShareLinks {
+    links: RwLock<HashMap<String, ShareLink>>,
+}
+
+impl ShareLinks {
+    pub fn create(
+        &self,
+        bridge_id: Uuid,
+        owner: &str,
+        session_id: &str,
+        scope: ShareScope,
+        ttl: Duration,
+    ) -> ShareLink {
+        let link = ShareLink {
+            token: Uuid::new_v4().simple().to_string(),
+            bridge_id,
+            owner: owner.to_string(),
+            session_id: session_id.to_string(),
+            scope,
+            expires_at: Utc::now() + ttl,
+        };
+
+        let mut links = self.links.write().unwrap();
+        links.retain(|_, l| !l.is_expired());
+        links.insert(link.token.clone(), link.clone());
+        link
+    }
+
+    /// Look up a link that has not expired yet
+    pub fn get(&self, token: &str) -> Option<ShareLink> {
+        self.links
+            .read()
+            .unwrap()
+            .get(token)
+            .filter(|l| !l.is_expired())
+            .cloned()
+    }
+
+    pub fn list_for(&self, owner: &str) -> Vec<ShareLink> {
+        self.links
+            .read()
+            .unwrap()
+            .values()
+            .filter(|l| l.owner == owner && !l.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    pub fn revoke(&self, token: &str) -> Option<ShareLink> {
+        self.links.write().unwrap().remove(token)
+    }
+}
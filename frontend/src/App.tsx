@@ -1,18 +1,37 @@
 import { useState, useEffect } from 'react';
 import { AuthPage } from './components/AuthPage';
 import { ConsolePage } from './components/ConsolePage';
+import { apiClient } from './api/client';
 
 function App() {
   const [token, setToken] = useState<string | null>(null);
   const [username, setUsername] = useState<string>('');
+  const [ssoError, setSsoError] = useState<string>('');
 
   useEffect(() => {
+    try {
+      const sso = apiClient.completeSsoLogin();
+      if (sso) {
+        localStorage.setItem('token', sso.token);
+        localStorage.setItem('username', sso.username);
+        localStorage.setItem('ssh_auth', 'server');
+        setToken(sso.token);
+        setUsername(sso.username);
+        return;
+      }
+    } catch (error) {
+      setSsoError(error instanceof Error ? error.message : 'SSO login failed');
+      return;
+    }
+
     const storedToken = localStorage.getItem('token');
     const storedUsername = localStorage.getItem('username');
 
     if (storedToken && storedUsername) {
       setToken(storedToken);
       setUsername(storedUsername);
+      // Access tokens are short-lived; get a fresh one (and restart the refresh timer)
+      apiClient.refresh().catch(() => handleLogout());
     }
   }, []);
 
@@ -31,22 +50,31 @@ function App() {
     // 3. Implement server-side session management (no client-side password storage)
     //
     // TODO: Remove this before production deployment
-    localStorage.setItem('password', password);
+    // With SSH certificates the server never needs the password, so it is not kept at all
+    if (password) {
+      localStorage.setItem('password', password);
+      localStorage.removeItem('ssh_auth');
+    } else {
+      localStorage.removeItem('password');
+      localStorage.setItem('ssh_auth', 'server');
+    }
 
     setToken(newToken);
     setUsername(newUsername);
   };
 
   const handleLogout = () => {
+    apiClient.logout().catch((error) => console.error('Logout failed:', error));
     localStorage.removeItem('token');
     localStorage.removeItem('username');
     localStorage.removeItem('password');
+    localStorage.removeItem('ssh_auth');
     setToken(null);
     setUsername('');
   };
 
   if (!token) {
-    return <AuthPage onLogin={handleLogin} />;
+    return <AuthPage onLogin={handleLogin} initialError={ssoError} />;
   }
 
   return <ConsolePage username={username} onLogout={handleLogout} />;
@@ -7,12 +7,22 @@ export interface LoginRequest {
 
 export interface LoginResponse {
   token: string;
+  refresh_token: string;
+  expires_in: number;
   username: string;
 }
 
+export interface AuthProviders {
+  password: boolean;
+  oidc: boolean;
+  ssh_certificates: boolean;
+}
+
 export interface TerminalSession {
   id: string;
   title: string;
+  /** Pinned against the idle session reaper */
+  keep_alive?: boolean;
 }
 
 export interface CreateTerminalSessionRequest {
@@ -20,6 +30,16 @@ export interface CreateTerminalSessionRequest {
   title: string;
 }
 
+export interface TerminalTicketRequest {
+  session_id: string;
+  host?: string;
+}
+
+export interface TerminalTicket {
+  ticket: string;
+  expires_in: number;
+}
+
 function getAuthHeaders(includePassword = false): HeadersInit {
   const token = localStorage.getItem('token');
   const headers: HeadersInit = {
@@ -37,6 +57,20 @@ function getAuthHeaders(includePassword = false): HeadersInit {
   return headers;
 }
 
+let refreshTimer: ReturnType<typeof setTimeout> | undefined;
+
+// Store a new token pair and refresh it a minute before the access token expires
+function storeTokens(response: LoginResponse) {
+  localStorage.setItem('token', response.token);
+  localStorage.setItem('refresh_token', response.refresh_token);
+
+  clearTimeout(refreshTimer);
+  const delay = Math.max(response.expires_in - 60, 10) * 1000;
+  refreshTimer = setTimeout(() => {
+    apiClient.refresh().catch((error) => console.error('Token refresh failed:', error));
+  }, delay);
+}
+
 export const apiClient = {
   async login(data: LoginRequest): Promise<LoginResponse> {
     const response = await fetch(`${API_BASE_URL}/api/auth/login`, {
@@ -52,6 +86,95 @@ export const apiClient = {
       throw new Error(error || 'Login failed');
     }
 
+    const result: LoginResponse = await response.json();
+    storeTokens(result);
+    return result;
+  },
+
+  async providers(): Promise<AuthProviders> {
+    const response = await fetch(`${API_BASE_URL}/api/auth/providers`);
+    if (!response.ok) {
+      return { password: true, oidc: false, ssh_certificates: false };
+    }
+    return response.json();
+  },
+
+  // SSO: the backend redirects to the identity provider and back to the frontend
+  ssoLoginUrl(): string {
+    return `${API_BASE_URL}/api/auth/oidc/login`;
+  },
+
+  // Pick up tokens the SSO callback left in the URL fragment
+  completeSsoLogin(): LoginResponse | null {
+    const params = new URLSearchParams(window.location.hash.slice(1));
+    const error = params.get('sso_error');
+    const token = params.get('token');
+    if (!error && !token) {
+      return null;
+    }
+
+    window.history.replaceState(null, '', window.location.pathname + window.location.search);
+    if (error) {
+      throw new Error(error);
+    }
+
+    const result: LoginResponse = {
+      token: token!,
+      refresh_token: params.get('refresh_token') ?? '',
+      expires_in: Number(params.get('expires_in') ?? 0),
+      username: params.get('username') ?? '',
+    };
+    storeTokens(result);
+    return result;
+  },
+
+  async refresh(): Promise<LoginResponse> {
+    const refreshToken = localStorage.getItem('refresh_token');
+    if (!refreshToken) {
+      throw new Error('Not logged in');
+    }
+
+    const response = await fetch(`${API_BASE_URL}/api/auth/refresh`, {
+      method: 'POST',
+      headers: {
+        'Content-Type': 'application/json',
+      },
+      body: JSON.stringify({ refresh_token: refreshToken }),
+    });
+
+    if (!response.ok) {
+      throw new Error('Session expired');
+    }
+
+    const result: LoginResponse = await response.json();
+    storeTokens(result);
+    return result;
+  },
+
+  async logout(): Promise<void> {
+    clearTimeout(refreshTimer);
+    try {
+      await fetch(`${API_BASE_URL}/api/auth/logout`, {
+        method: 'POST',
+        headers: getAuthHeaders(),
+      });
+    } finally {
+      localStorage.removeItem('refresh_token');
+    }
+  },
+
+  // Single-use, short-lived ticket for opening a terminal WebSocket
+  async createTerminalTicket(data: TerminalTicketRequest): Promise<TerminalTicket> {
+    const response = await fetch(`${API_BASE_URL}/api/terminal-tickets`, {
+      method: 'POST',
+      headers: getAuthHeaders(),
+      body: JSON.stringify(data),
+    });
+
+    if (!response.ok) {
+      throw new Error('Failed to authorize terminal connection');
+    }
+
     return response.json();
   },
 
@@ -92,4 +215,15 @@ export const apiClient = {
       throw new Error('Failed to delete terminal session');
     }
   },
+
+  async setTerminalSessionKeepAlive(sessionId: string, keepAlive: boolean): Promise<void> {
+    const response = await fetch(`${API_BASE_URL}/api/terminal-sessions/${sessionId}/keep-alive`, {
+      method: keepAlive ? 'PUT' : 'DELETE',
+      headers: getAuthHeaders(true), // Include SSH password
+    });
+
+    if (!response.ok && response.status !== 204) {
+      throw new Error('Failed to update terminal session');
+    }
+  },
 };
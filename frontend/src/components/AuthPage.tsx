@@ -1,18 +1,37 @@
-import { useState } from 'react';
+import { useEffect, useState } from 'react';
 import { Button } from '@/components/ui/button';
 import { Input } from '@/components/ui/input';
 import { Label } from '@/components/ui/label';
 import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/components/ui/card';
-import { apiClient } from '@/api/client';
+import { apiClient, type AuthProviders } from '@/api/client';
 
 interface AuthPageProps {
   onLogin: (token: string, username: string, password: string) => void;
+  initialError?: string;
 }
 
-export function AuthPage({ onLogin }: AuthPageProps) {
+export function AuthPage({ onLogin, initialError }: AuthPageProps) {
   const [loginData, setLoginData] = useState({ username: '', password: '' });
-  const [error, setError] = useState<string>('');
+  const [error, setError] = useState<string>(initialError ?? '');
   const [loading, setLoading] = useState(false);
+  const [providers, setProviders] = useState<AuthProviders>({
+    password: true,
+    oidc: false,
+    ssh_certificates: false,
+  });
+
+  useEffect(() => {
+    apiClient
+      .providers()
+      .then(setProviders)
+      .catch(() => undefined);
+  }, []);
+
+  useEffect(() => {
+    if (initialError) {
+      setError(initialError);
+    }
+  }, [initialError]);
 
   const handleLogin = async (e: React.FormEvent) => {
     e.preventDefault();
@@ -21,7 +40,9 @@ export function AuthPage({ onLogin }: AuthPageProps) {
 
     try {
       const response = await apiClient.login(loginData);
-      onLogin(response.token, response.username, loginData.password);
+      // The backend signs SSH certificates itself; the password is only needed to log in
+      const password = providers.ssh_certificates ? '' : loginData.password;
+      onLogin(response.token, response.username, password);
     } catch (err) {
       setError(err instanceof Error ? err.message : 'Login failed');
     } finally {
@@ -61,6 +82,16 @@ export function AuthPage({ onLogin }: AuthPageProps) {
             <Button type="submit" className="w-full" disabled={loading}>
               {loading ? 'Logging in...' : 'Login'}
             </Button>
+            {providers.oidc && (
+              <Button
+                type="button"
+                variant="outline"
+                className="w-full"
+                onClick={() => window.location.assign(apiClient.ssoLoginUrl())}
+              >
+                Login with SSO
+              </Button>
+            )}
           </form>
         </CardContent>
       </Card>
@@ -5,6 +5,10 @@ import { WebLinksAddon } from '@xterm/addon-web-links';
 import '@xterm/xterm/css/xterm.css';
 import { VirtualKeyboard } from './VirtualKeyboard';
 import { useIsMobile } from '@/hooks/useIsMobile';
+import { apiClient } from '@/api/client';
+
+// Pause before attaching again after the server restarted
+const RECONNECT_DELAY_MS = 3000;
 
 interface TerminalProps {
   sessionId?: string;
@@ -133,68 +137,130 @@ export function Terminal({ sessionId }: TerminalProps) {
     }, 0);
 
     // Connect to WebSocket
-    const token = localStorage.getItem('token');
     const apiBaseUrl = import.meta.env.VITE_API_BASE_URL || 'http://localhost:8080';
     // Convert HTTP/HTTPS URL to WS/WSS
     const wsUrl = apiBaseUrl.replace(/^http/, 'ws');
-    const ws = new WebSocket(`${wsUrl}/ws/terminal?token=${token}&session_id=${sessionId}`);
-    ws.binaryType = 'arraybuffer'; // Receive binary data as ArrayBuffer
-    wsRef.current = ws;
-
-    ws.onopen = () => {
-      console.log('WebSocket connected');
-      // Send password as first message for SSH authentication
-      const password = localStorage.getItem('password');
-      if (password) {
-        ws.send(password);
-      } else {
-        xterm.writeln('\r\nError: No password found. Please login again.');
-      }
+    const terminalSessionId = sessionId ?? 'default';
+    let disposed = false;
+    // Set when the server announced a restart; the tmux session survives, so attach again
+    let restarting = false;
+
+    const connect = (ticket: string) => {
+      const socket = new WebSocket(
+        `${wsUrl}/ws/terminal?ticket=${encodeURIComponent(ticket)}&session_id=${encodeURIComponent(terminalSessionId)}`
+      );
+      socket.binaryType = 'arraybuffer'; // Receive binary data as ArrayBuffer
+      wsRef.current = socket;
+
+      socket.onopen = () => {
+        console.log('WebSocket connected');
+        // Send password as first message for SSH authentication
+        // (not needed when the server holds the credentials: SSO key or CA certificate)
+        const password = localStorage.getItem('password');
+        if (password) {
+          socket.send(password);
+        } else if (localStorage.getItem('ssh_auth') !== 'server') {
+          xterm.writeln('\r\nError: No password found. Please login again.');
+        }
 
-      // Send initial terminal size after connection
-      setTimeout(() => {
-        const cols = xterm.cols;
-        const rows = xterm.rows;
-        const resizeMessage = JSON.stringify({ type: 'resize', cols, rows });
-        if (ws.readyState === WebSocket.OPEN) {
-          ws.send(resizeMessage);
-          console.log(`Sent initial terminal size: ${cols}x${rows}`);
+        // Send initial terminal size after connection
+        setTimeout(() => {
+          const cols = xterm.cols;
+          const rows = xterm.rows;
+          const resizeMessage = JSON.stringify({ type: 'resize', cols, rows });
+          if (socket.readyState === WebSocket.OPEN) {
+            socket.send(resizeMessage);
+            console.log(`Sent initial terminal size: ${cols}x${rows}`);
+          }
+        }, 100);
+      };
+
+      socket.onmessage = async (event) => {
+        if (typeof event.data === 'string') {
+          // JSON text frames are control notifications; everything else is terminal output
+          if (event.data.startsWith('{')) {
+            try {
+              const frame = JSON.parse(event.data);
+              if (frame.type === 'viewer_joined') {
+                xterm.writeln(`\r\n\x1b[2m[${frame.viewer} joined this terminal (${frame.scope})]\x1b[0m`);
+              } else if (frame.type === 'viewer_left') {
+                xterm.writeln(`\r\n\x1b[2m[${frame.viewer} left this terminal]\x1b[0m`);
+              } else if (frame.type === 'control_requested') {
+                xterm.writeln(`\r\n\x1b[2m[${frame.from} is asking for the keyboard]\x1b[0m`);
+              } else if (frame.type === 'idle_warning') {
+                const what = frame.reason === 'idle' ? 'Idle terminal' : 'Terminal';
+                xterm.writeln(`\r\n\x1b[33m[${what} closes in ${frame.seconds_left}s]\x1b[0m`);
+              } else if (frame.type === 'server_restarting') {
+                xterm.writeln(`\r\n\x1b[33m[${frame.message}]\x1b[0m`);
+                // Detach from tmux right away instead of waiting to be cut off
+                restarting = true;
+                socket.close(1000);
+              } else if (frame.type === 'exit') {
+                restarting = frame.reason === 'server_shutdown';
+                // Final frame before the server closes the socket with `frame.code`
+                xterm.writeln(`\r\n\x1b[31m${frame.message}\x1b[0m`);
+              }
+              return;
+            } catch {
+              // Not a control frame
+            }
+          }
+          xterm.write(event.data);
+        } else if (event.data instanceof Blob) {
+          const text = await event.data.text();
+          xterm.write(text);
+        } else if (event.data instanceof ArrayBuffer) {
+          const decoder = new TextDecoder();
+          xterm.write(decoder.decode(event.data));
         }
-      }, 100);
-    };
+      };
 
-    ws.onmessage = async (event) => {
-      if (typeof event.data === 'string') {
-        xterm.write(event.data);
-      } else if (event.data instanceof Blob) {
-        const text = await event.data.text();
-        xterm.write(text);
-      } else if (event.data instanceof ArrayBuffer) {
-        const decoder = new TextDecoder();
-        xterm.write(decoder.decode(event.data));
-      }
-    };
+      socket.onerror = (error) => {
+        xterm.writeln(`\r\nWebSocket error: ${error}`);
+      };
 
-    ws.onerror = (error) => {
-      xterm.writeln(`\r\nWebSocket error: ${error}`);
+      socket.onclose = () => {
+        xterm.writeln('\r\nConnection closed');
+        if (restarting && !disposed) {
+          restarting = false;
+          xterm.writeln('\x1b[2m[Reconnecting...]\x1b[0m');
+          setTimeout(open, RECONNECT_DELAY_MS);
+        }
+      };
     };
 
-    ws.onclose = () => {
-      xterm.writeln('\r\nConnection closed');
+    // Connect with a one-time ticket so the access token never shows up in URLs or access logs
+    const open = () => {
+      if (disposed) {
+        return;
+      }
+      apiClient
+        .createTerminalTicket({ session_id: terminalSessionId })
+        .then(({ ticket }) => {
+          if (!disposed) {
+            connect(ticket);
+          }
+        })
+        .catch((error) => {
+          xterm.writeln(`\r\nError: ${error instanceof Error ? error.message : error}`);
+        });
     };
+    open();
 
     // Send terminal input to WebSocket
     xterm.onData((data) => {
-      if (ws.readyState === WebSocket.OPEN) {
-        ws.send(data);
+      const socket = wsRef.current;
+      if (socket?.readyState === WebSocket.OPEN) {
+        socket.send(data);
       }
     });
 
     // Send terminal resize to backend
     const resizeDisposable = xterm.onResize(({ cols, rows }) => {
       const resizeMessage = JSON.stringify({ type: 'resize', cols, rows });
-      if (ws.readyState === WebSocket.OPEN) {
-        ws.send(resizeMessage);
+      const socket = wsRef.current;
+      if (socket?.readyState === WebSocket.OPEN) {
+        socket.send(resizeMessage);
         console.log(`Terminal resized: ${cols}x${rows}`);
       }
     });
@@ -219,7 +285,9 @@ export function Terminal({ sessionId }: TerminalProps) {
       }
       resizeDisposable.dispose();
       window.removeEventListener('resize', handleResize);
-      ws.close();
+      disposed = true;
+      wsRef.current?.close();
+      wsRef.current = null;
       xterm.dispose();
     };
   }, [isMobile, sessionId]);